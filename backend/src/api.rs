@@ -1,16 +1,58 @@
+use actix_files::{HttpRange, NamedFile, file_extension_to_mime};
 use actix_multipart::Multipart;
-use actix_web::{HttpResponse, Responder, Result, get, post, web};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ETag, EntityTag,
+    ExtendedValue, Header, IfNoneMatch,
+};
+use actix_web::{
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder, Result, delete, get, patch, post,
+    put, web,
+};
+use chrono::Utc;
 use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
 
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::{Mutex};
 
+use crate::auth::{CurrentUser, SESSION_COOKIE, Teacher, session_token};
 use crate::db::*;
 use crate::error::SubmError;
+use crate::limits::UploadLimits;
+use crate::paths;
+use crate::revisions::diff_revisions;
 
-use log::error;
+pub type DbState = Arc<SubmDb>;
 
-pub type DbState = Arc<Mutex<SubmDb>>;
+/// Header carrying the cursor of the next page of a listing
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+
+// Lists the page's items, pointing to the next page if there is one
+fn page_response<T: Serialize>(page: Page<T>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    if let Some(cursor) = page.next_cursor {
+        response.insert_header((NEXT_CURSOR_HEADER, cursor.to_string()));
+    }
+    response.json(page.items)
+}
+
+/// Reports request bodies that fail to parse in the usual JSON error shape
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|e, _| SubmError::BadRequest(e.to_string()).into())
+}
+
+/// Reports query strings that fail to parse in the usual JSON error shape
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default().error_handler(|e, _| SubmError::BadRequest(e.to_string()).into())
+}
+
+/// Reports path parameters that fail to parse, e.g. `/api/problems/abc`, as
+/// missing resources in the usual JSON error shape
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default().error_handler(|_, _| SubmError::NotFound("Resource").into())
+}
 
 #[get("/api/")]
 async fn index() -> impl Responder {
@@ -20,54 +62,389 @@ async fn index() -> impl Responder {
     }))
 }
 
+// Account endpoints
+#[post("/api/login")]
+async fn login(db: web::Data<DbState>, credentials: web::Json<Login>) -> Result<impl Responder> {
+    let (token, user) = db
+        .login(credentials.into_inner())
+        .await?
+        .ok_or(SubmError::Unauthorized)?;
+
+    let cookie = Cookie::build(SESSION_COOKIE, token.clone())
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .finish();
+    Ok(HttpResponse::Ok().cookie(cookie).json(serde_json::json!({
+        "token": token,
+        "user": user,
+    })))
+}
+
+#[post("/api/logout")]
+async fn logout(db: web::Data<DbState>, req: HttpRequest) -> Result<impl Responder> {
+    if let Some(token) = session_token(&req) {
+        db.logout(&token).await?;
+    }
+
+    let mut cookie = Cookie::build(SESSION_COOKIE, "").path("/").finish();
+    cookie.make_removal();
+    Ok(HttpResponse::NoContent().cookie(cookie).finish())
+}
+
+#[get("/api/me")]
+async fn get_me(user: CurrentUser) -> impl Responder {
+    HttpResponse::Ok().json(user.0)
+}
+
+#[post("/api/users")]
+async fn create_user(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    user: web::Json<CreateUser>,
+) -> Result<impl Responder> {
+    let id = db.create_user(user.into_inner()).await?;
+    Ok(HttpResponse::Created().json(serde_json::json!({"id": id})))
+}
+
+// Course endpoints
+#[post("/api/courses")]
+async fn create_course(
+    db: web::Data<DbState>,
+    Teacher(user): Teacher,
+    course: web::Json<CreateCourse>,
+) -> Result<impl Responder> {
+    let id = db.create_course(course.into_inner(), user.id).await?;
+    Ok(HttpResponse::Created().json(serde_json::json!({"id": id})))
+}
+
+#[get("/api/courses")]
+async fn get_courses(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    range: web::Query<TimeRange>,
+) -> Result<impl Responder> {
+    range.validate()?;
+    let courses = db.get_courses(user.course_filter(), &range).await?;
+    Ok(HttpResponse::Ok().json(courses))
+}
+
+#[get("/api/courses/{id}")]
+async fn get_course(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let course_id = path.into_inner();
+    let course = db
+        .get_course_by_id(course_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+    Ok(HttpResponse::Ok().json(course))
+}
+
+#[post("/api/courses/{id}/members")]
+async fn add_course_member(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<i64>,
+    member: web::Json<AddCourseMember>,
+) -> Result<impl Responder> {
+    let course_id = path.into_inner();
+
+    // Verify course and user exist
+    db.get_course_by_id(course_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+    db.get_user_by_id(member.user)
+        .await?
+        .ok_or(SubmError::NotFound("User"))?;
+
+    db.add_course_member(course_id, member.user).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/api/courses/{id}/members")]
+async fn get_course_members(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let course_id = path.into_inner();
+    db.get_course_by_id(course_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+
+    let members = db.get_course_members(course_id).await?;
+    Ok(HttpResponse::Ok().json(members))
+}
+
+#[get("/api/courses/{id}/problems")]
+async fn get_course_problems(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+    query: web::Query<ProblemQuery>,
+) -> Result<impl Responder> {
+    let course_id = path.into_inner();
+    let query = ProblemQuery {
+        course: Some(course_id),
+        ..query.into_inner()
+    };
+    query.validate()?;
+    db.get_course_by_id(course_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+
+    let problems = db.get_problems(user.course_filter(), &query).await?;
+    Ok(page_response(problems))
+}
+
+#[get("/api/courses/{id}/submissions")]
+async fn get_course_submissions(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+    query: web::Query<SubmissionQuery>,
+) -> Result<impl Responder> {
+    let course_id = path.into_inner();
+    let query = SubmissionQuery {
+        course: Some(course_id),
+        ..query.into_inner()
+    };
+    query.validate()?;
+    db.get_course_by_id(course_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+
+    let submissions = db.get_submissions(user.submission_filter(), &query).await?;
+    Ok(page_response(submissions))
+}
+
 // Problem endpoints
 #[post("/api/problems")]
 async fn create_problem(
     db: web::Data<DbState>,
+    _teacher: Teacher,
     problem: web::Json<CreateProblem>,
 ) -> Result<impl Responder> {
-    let db = db.lock().await;
-    match db.create_problem(problem.into_inner()).await {
-        Ok(id) => Ok(HttpResponse::Created().json(serde_json::json!({"id": id}))),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
-    }
+    let problem = problem.into_inner();
+
+    // Verify course exists
+    db.get_course_by_id(problem.course, None)
+        .await?
+        .ok_or(SubmError::NotFound("Course"))?;
+
+    let id = db.create_problem(problem).await?;
+    Ok(HttpResponse::Created().json(serde_json::json!({"id": id})))
 }
 
 #[get("/api/problems")]
-async fn get_problems(db: web::Data<DbState>) -> Result<impl Responder> {
-    let db = db.lock().await;
-    match db.get_problems().await {
-        Ok(problems) => Ok(HttpResponse::Ok().json(problems)),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
-    }
+async fn get_problems(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    query: web::Query<ProblemQuery>,
+) -> Result<impl Responder> {
+    query.validate()?;
+    let problems = db.get_problems(user.course_filter(), &query).await?;
+    Ok(page_response(problems))
+}
+
+/// Attempts per problem and whether one was accepted; students only see
+/// their own submissions counted
+#[get("/api/problems/stats")]
+async fn get_problem_stats(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+) -> Result<impl Responder> {
+    let stats = db.get_problem_stats(user.submission_filter()).await?;
+    Ok(HttpResponse::Ok().json(stats))
 }
 
 #[get("/api/problems/{id}")]
-async fn get_problem(db: web::Data<DbState>, path: web::Path<i64>) -> Result<impl Responder> {
+async fn get_problem(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
     let problem_id = path.into_inner();
-    let db = db.lock().await;
-    match db.get_problem_by_id(problem_id).await {
-        Ok(Some(problem)) => Ok(HttpResponse::Ok().json(problem)),
-        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "error": "Problem not found"
-        }))),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
+    let problem = db
+        .get_problem_by_id(problem_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+    Ok(HttpResponse::Ok().json(problem))
+}
+
+#[put("/api/problems/{id}")]
+async fn update_problem(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<i64>,
+    problem: web::Json<UpdateProblem>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    if !db.update_problem(problem_id, problem.into_inner()).await? {
+        return Err(SubmError::NotFound("Problem").into());
+    }
+
+    let problem = db
+        .get_problem_by_id(problem_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+    Ok(HttpResponse::Ok().json(problem))
+}
+
+#[patch("/api/problems/{id}")]
+async fn patch_problem(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<i64>,
+    changes: web::Json<PatchProblem>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    let current = db
+        .get_problem_by_id(problem_id, None)
+        .await?
+        .filter(|problem| !problem.deleted)
+        .ok_or(SubmError::NotFound("Problem"))?;
+
+    if !db.update_problem(problem_id, changes.into_inner().apply(current)).await? {
+        return Err(SubmError::NotFound("Problem").into());
     }
+
+    let problem = db
+        .get_problem_by_id(problem_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+    Ok(HttpResponse::Ok().json(problem))
+}
+
+#[get("/api/problems/{id}/revisions")]
+async fn get_problem_revisions(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    db.get_problem_by_id(problem_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+
+    let revisions = db.get_problem_revisions(problem_id).await?;
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+#[derive(Deserialize)]
+struct DiffQuery {
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+// Compares two revisions by ID. Without `to` the current revision is used,
+// and without `from` the one just before `to`.
+#[get("/api/problems/{id}/revisions/diff")]
+async fn get_problem_revision_diff(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+    query: web::Query<DiffQuery>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    db.get_problem_by_id(problem_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+
+    let revisions = db.get_problem_revisions(problem_id).await?;
+    let position = |id: i64| {
+        revisions
+            .iter()
+            .position(|revision| revision.id == id)
+            .ok_or(SubmError::NotFound("Revision"))
+    };
+
+    let to = match query.to {
+        Some(id) => position(id)?,
+        None => revisions.len().checked_sub(1).ok_or(SubmError::NotFound("Revision"))?,
+    };
+    let from = match query.from {
+        Some(id) => position(id)?,
+        None => to.saturating_sub(1),
+    };
+
+    Ok(HttpResponse::Ok().json(diff_revisions(&revisions[from], &revisions[to])))
+}
+
+// Students only see their own extension
+#[get("/api/problems/{id}/extensions")]
+async fn get_extensions(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    db.get_problem_by_id(problem_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+
+    let extensions = db.get_extensions(problem_id, user.submission_filter()).await?;
+    Ok(HttpResponse::Ok().json(extensions))
+}
+
+#[put("/api/problems/{id}/extensions/{student}")]
+async fn grant_extension(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<(i64, i64)>,
+    extension: web::Json<GrantExtension>,
+) -> Result<impl Responder> {
+    let (problem_id, student) = path.into_inner();
+    db.get_problem_by_id(problem_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+    db.get_user_by_id(student)
+        .await?
+        .ok_or(SubmError::NotFound("User"))?;
+
+    let extension = db
+        .grant_extension(problem_id, student, extension.into_inner())
+        .await?;
+    Ok(HttpResponse::Ok().json(extension))
+}
+
+#[delete("/api/problems/{id}/extensions/{student}")]
+async fn revoke_extension(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<(i64, i64)>,
+) -> Result<impl Responder> {
+    let (problem_id, student) = path.into_inner();
+    if !db.revoke_extension(problem_id, student).await? {
+        return Err(SubmError::NotFound("Extension").into());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Problems with submissions are only marked deleted, so the submissions
+// keep showing what they answered
+#[delete("/api/problems/{id}")]
+async fn delete_problem(
+    db: web::Data<DbState>,
+    _teacher: Teacher,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let problem_id = path.into_inner();
+    if !db.delete_problem(problem_id).await? {
+        return Err(SubmError::NotFound("Problem").into());
+    }
+    Ok(HttpResponse::NoContent().finish())
 }
 
 // Submission endpoints
 #[post("/api/problems/{id}/submissions")]
 async fn create_submission(
     db: web::Data<DbState>,
+    limits: web::Data<UploadLimits>,
+    CurrentUser(user): CurrentUser,
     path: web::Path<i64>,
     mut payload: Multipart,
 ) -> Result<impl Responder> {
@@ -75,7 +452,28 @@ async fn create_submission(
     let mut comment = String::new();
     let mut files = Vec::new();
 
-    // Parse multipart form data
+    // Verify problem exists in one of the caller's courses
+    let problem = db
+        .get_problem_by_id(problem_id, user.course_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Problem"))?;
+    if problem.deleted {
+        return Err(
+            SubmError::Conflict("Problem no longer accepts submissions".to_string()).into(),
+        );
+    }
+    let extension = db
+        .get_extensions(problem_id, Some(user.id))
+        .await?
+        .pop()
+        .map(|extension| extension.due_at);
+    let now = Utc::now();
+    let schedule = problem.schedule.clone().with_extension(extension);
+    schedule.check_open(now)?;
+    let limits = limits.for_problem(&problem);
+    let mut total_size: u64 = 0;
+
+    // Parse multipart form data, streaming files to the staging area
     while let Some(item) = payload.next().await {
         let mut field = item?;
         let field_name = field.name().to_string();
@@ -84,140 +482,330 @@ async fn create_submission(
             "comment" => {
                 let mut data = Vec::new();
                 while let Some(chunk) = field.next().await {
-                    data.extend_from_slice(&chunk?);
+                    let chunk = chunk?;
+                    total_size += chunk.len() as u64;
+                    if total_size > limits.max_total_size {
+                        return Err(SubmError::LimitExceeded {
+                            limit: "max_total_size",
+                            max: limits.max_total_size,
+                        }
+                        .into());
+                    }
+                    data.extend_from_slice(&chunk);
                 }
-                comment = String::from_utf8(data).unwrap_or_default();
+                comment = String::from_utf8(data)
+                    .map_err(|_| SubmError::Validation("Comment must be UTF-8 text".to_string()))?;
             }
             name if name.starts_with("file") => {
-                let filename = field
+                // The file name may carry a relative path to keep folders
+                // apart; files sent without one are named once all are in
+                let path = field
                     .content_disposition()
                     .get_filename()
-                    .unwrap_or("unknown")
-                    .to_string();
+                    .map(paths::sanitize_path)
+                    .transpose()?;
+
+                if files.len() as u64 >= limits.max_files {
+                    return Err(SubmError::LimitExceeded {
+                        limit: "max_files",
+                        max: limits.max_files,
+                    }
+                    .into());
+                }
+                if !limits.allows_file(path.as_deref().map_or("", paths::file_name)) {
+                    let filename = path.unwrap_or_else(|| "An unnamed file".to_string());
+                    return Err(SubmError::DisallowedFile { filename }.into());
+                }
 
-                let mut file_data = Vec::new();
+                let mut writer = db.blob_writer().await.map_err(SubmError::Storage)?;
                 while let Some(chunk) = field.next().await {
-                    file_data.extend_from_slice(&chunk?);
+                    let chunk = chunk?;
+                    if writer.size() + chunk.len() as u64 > limits.max_file_size {
+                        return Err(SubmError::LimitExceeded {
+                            limit: "max_file_size",
+                            max: limits.max_file_size,
+                        }
+                        .into());
+                    }
+                    total_size += chunk.len() as u64;
+                    if total_size > limits.max_total_size {
+                        return Err(SubmError::LimitExceeded {
+                            limit: "max_total_size",
+                            max: limits.max_total_size,
+                        }
+                        .into());
+                    }
+                    writer.write(&chunk).await.map_err(SubmError::Storage)?;
                 }
-                files.push((filename, file_data));
+                files.push((path, writer.finish().await.map_err(SubmError::Storage)?));
             }
             _ => {}
         }
     }
 
-    let submission = CreateSubmission { comment };
+    let submission = CreateSubmission {
+        comment,
+        late: schedule.is_late(now),
+    };
 
-    // Verify problem exists
-    let db = db.lock().await;
-    match db.get_problem_by_id(problem_id).await {
-        Ok(None) => {
-            return Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Problem not found"
-            })));
-        }
-        Err(e) => {
-            error!("{}", e);
-            return Err(SubmError::GenericError.into());
-        }
-        _ => {}
-    }
-
-    match db
-        .create_submission_with_files(problem_id, submission, files)
-        .await
-    {
-        Ok(id) => Ok(HttpResponse::Created().json(serde_json::json!({"id": id}))),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
-    }
+    let files = paths::name_unnamed(files);
+    let id = db
+        .create_submission_with_files(problem_id, user.id, submission, files)
+        .await?;
+    Ok(HttpResponse::Created().json(serde_json::json!({"id": id})))
 }
 
 #[get("/api/submissions")]
-async fn get_submissions(db: web::Data<DbState>) -> Result<impl Responder> {
-    let db = db.lock().await;
-    match db.get_submissions().await {
-        Ok(submissions) => Ok(HttpResponse::Ok().json(submissions)),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
-    }
+async fn get_submissions(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    query: web::Query<SubmissionQuery>,
+) -> Result<impl Responder> {
+    query.validate()?;
+    let submissions = db.get_submissions(user.submission_filter(), &query).await?;
+    Ok(page_response(submissions))
 }
 
 #[get("/api/submissions/{id}")]
-async fn get_submission(db: web::Data<DbState>, path: web::Path<i64>) -> Result<impl Responder> {
+async fn get_submission(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
     let submission_id = path.into_inner();
-    let db = db.lock().await;
-    match db.get_submission_by_id(submission_id).await {
-        Ok(Some(submission)) => Ok(HttpResponse::Ok().json(submission)),
-        Ok(None) => Ok(HttpResponse::NotFound().json(serde_json::json!({
-            "error": "Submission not found"
-        }))),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
-    }
+    let submission = db
+        .get_submission_by_id(submission_id, user.submission_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Submission"))?;
+    Ok(HttpResponse::Ok().json(submission))
+}
+
+#[get("/api/submissions/{id}/tree")]
+async fn get_submission_tree(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let submission_id = path.into_inner();
+    let submission = db
+        .get_submission_by_id(submission_id, user.submission_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Submission"))?;
+    Ok(HttpResponse::Ok().json(paths::file_tree(&submission.files)))
 }
 
 #[post("/api/submissions/{id}/feedback")]
 async fn create_feedback(
     db: web::Data<DbState>,
+    Teacher(teacher): Teacher,
     path: web::Path<i64>,
     feedback: web::Json<CreateFeedback>,
 ) -> Result<impl Responder> {
     let submission_id = path.into_inner();
 
     // Verify submission exists
-    let db = db.lock().await;
-    match db.get_submission_by_id(submission_id).await {
-        Ok(None) => {
-            return Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": "Submission not found"
-            })));
-        }
-        Err(e) => {
-            error!("{}", e);
-            return Err(SubmError::GenericError.into());
-        }
-        _ => {}
+    db.get_submission_by_id(submission_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Submission"))?;
+
+    let feedback_id = db
+        .create_feedback(submission_id, teacher.id, feedback.into_inner())
+        .await?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "id": feedback_id,
+    })))
+}
+
+// Looks up feedback that `teacher` may change: their own, or feedback
+// written before authorship was recorded
+async fn own_feedback(db: &DbState, teacher: &User, id: i64) -> Result<Feedback, SubmError> {
+    let feedback = db
+        .get_feedback_by_id(id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Feedback"))?;
+    match feedback.author {
+        Some(author) if author != teacher.id => Err(SubmError::Forbidden),
+        _ => Ok(feedback),
     }
+}
 
-    match db
-        .create_feedback(submission_id, feedback.into_inner())
-        .await
-    {
-        Ok(feedback_id) => Ok(HttpResponse::Created().json(serde_json::json!({
-            "id": feedback_id,
-        }))),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::GenericError.into())
-        },
+#[patch("/api/feedback/{id}")]
+async fn edit_feedback(
+    db: web::Data<DbState>,
+    Teacher(teacher): Teacher,
+    path: web::Path<i64>,
+    edit: web::Json<EditFeedback>,
+) -> Result<impl Responder> {
+    let feedback_id = path.into_inner();
+    own_feedback(&db, &teacher, feedback_id).await?;
+
+    if !db.edit_feedback(feedback_id, edit.into_inner()).await? {
+        return Err(SubmError::NotFound("Feedback").into());
     }
+    let feedback = db
+        .get_feedback_by_id(feedback_id, None)
+        .await?
+        .ok_or(SubmError::NotFound("Feedback"))?;
+    Ok(HttpResponse::Ok().json(feedback))
+}
+
+#[delete("/api/feedback/{id}")]
+async fn retract_feedback(
+    db: web::Data<DbState>,
+    Teacher(teacher): Teacher,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let feedback_id = path.into_inner();
+    own_feedback(&db, &teacher, feedback_id).await?;
+
+    if !db.retract_feedback(feedback_id).await? {
+        return Err(SubmError::NotFound("Feedback").into());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/api/feedback/{id}/edits")]
+async fn get_feedback_edits(
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<i64>,
+) -> Result<impl Responder> {
+    let feedback_id = path.into_inner();
+    db.get_feedback_by_id(feedback_id, user.submission_filter())
+        .await?
+        .ok_or(SubmError::NotFound("Feedback"))?;
+
+    let edits = db.get_feedback_edits(feedback_id).await?;
+    Ok(HttpResponse::Ok().json(edits))
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    /// Submission whose name for the file to send it under
+    submission: Option<i64>,
+}
+
+// Sends a blob from a store that isn't on local disk, answering Range
+// requests the way NamedFile does: only the first range is served
+async fn stored_blob_response(
+    req: &HttpRequest,
+    db: &DbState,
+    hash: &str,
+    mut resp: HttpResponseBuilder,
+) -> Result<HttpResponse> {
+    resp.insert_header((header::ACCEPT_RANGES, "bytes"));
+    let Some(range) = req.headers().get(header::RANGE) else {
+        let (size, stream) = db.blob_stream(hash).await?;
+        return Ok(resp.no_chunking(size).streaming(stream));
+    };
+
+    let size = db.blob_size(hash).await?;
+    let ranges = range
+        .to_str()
+        .ok()
+        .and_then(|range| HttpRange::parse(range, size).ok());
+    let Some(ranges) = ranges else {
+        return Ok(resp
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+            .finish());
+    };
+    let Some(range) = ranges.first() else {
+        let (size, stream) = db.blob_stream(hash).await?;
+        return Ok(resp.no_chunking(size).streaming(stream));
+    };
+
+    let stream = db.blob_range_stream(hash, range.start, range.length).await?;
+    let end = range.start + range.length - 1;
+    Ok(resp
+        .status(StatusCode::PARTIAL_CONTENT)
+        .insert_header((header::CONTENT_RANGE, format!("bytes {}-{}/{}", range.start, end, size)))
+        .no_chunking(range.length)
+        .streaming(stream))
 }
 
 #[get("/api/files/{hash}")]
-async fn get_file(db: web::Data<DbState>, path: web::Path<String>) -> Result<impl Responder> {
+async fn get_file(
+    req: HttpRequest,
+    db: web::Data<DbState>,
+    CurrentUser(user): CurrentUser,
+    path: web::Path<String>,
+    query: web::Query<FileQuery>,
+) -> Result<HttpResponse> {
     let hash = path.into_inner();
 
     let sha256_length = 64;
-    let chars_good = hash.chars().all(|c| c.is_digit(16));
+    let chars_good = hash.chars().all(|c| c.is_ascii_hexdigit());
     if !chars_good || hash.len() != sha256_length {
-        error!("{} is not a sha256 hash", &hash);
-        return Err(SubmError::FileNotFound.into());
+        return Err(SubmError::NotFound("File").into());
+    }
+
+    let name = db
+        .get_file_name(&hash, user.submission_filter(), query.submission)
+        .await?
+        .ok_or(SubmError::NotFound("File"))?;
+
+    // Blobs are named by their content, so the hash is a perfect ETag
+    let etag = EntityTag::new_strong(hash.clone());
+    if let Ok(IfNoneMatch::Items(tags)) = IfNoneMatch::parse(&req)
+        && tags.iter().any(|tag| tag.weak_eq(&etag))
+    {
+        return Ok(HttpResponse::NotModified().insert_header(ETag(etag)).finish());
     }
 
-    let db = db.lock().await;
-    match db.get_file_content(&hash).await {
-        Ok(content) => Ok(HttpResponse::Ok()
-            .content_type("application/octet-stream")
-            .body(content)),
-        Err(e) => {
-            error!("{}", e);
-            Err(SubmError::FileNotFound.into())
-        },
+    let extension = Path::new(&name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    // Header values should be ASCII, so other names get a stand-in next to
+    // the exact one
+    let fallback = name
+        .chars()
+        .map(|c| if c.is_ascii() && !c.is_ascii_control() { c } else { '_' })
+        .collect();
+    let mut parameters = vec![DispositionParam::Filename(fallback)];
+    if !name.is_ascii() {
+        parameters.push(DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_string()),
+            language_tag: None,
+            value: name.clone().into_bytes(),
+        }));
     }
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters,
+    };
+
+    // Blobs kept on local disk go through NamedFile, unless they have to be
+    // checked, which can only be done on the whole blob
+    let local_path = db.local_blob_path(&hash).filter(|_| !db.verifies_blobs());
+    let Some(local_path) = local_path else {
+        let mut resp = HttpResponse::Ok();
+        resp.content_type(file_extension_to_mime(extension))
+            .insert_header(disposition)
+            .insert_header(ETag(etag));
+        if db.verifies_blobs() {
+            // The length is announced up front, so a stream cut short by a
+            // failed check shows up as an error on the client
+            let (size, stream) = db.blob_stream(&hash).await?;
+            return Ok(resp.no_chunking(size).streaming(stream));
+        }
+        return stored_blob_response(&req, &db, &hash, resp).await;
+    };
+
+    // The file is known, so a missing blob means the store is damaged
+    let file = NamedFile::open_async(local_path)
+        .await
+        .map_err(SubmError::Storage)?;
+
+    // NamedFile takes care of Range requests; its own ETag comes from the
+    // file's inode and mtime, so it is swapped for the hash
+    let mut resp = file
+        .set_content_type(file_extension_to_mime(extension))
+        .set_content_disposition(disposition)
+        .use_etag(false)
+        .into_response(&req);
+    resp.headers_mut()
+        .insert(header::ETAG, etag.to_string().parse().expect("hash is a valid header value"));
+    Ok(resp)
 }
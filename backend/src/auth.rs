@@ -0,0 +1,91 @@
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{Error, FromRequest, HttpRequest, web};
+use argon2::Argon2;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use futures_util::future::LocalBoxFuture;
+
+use crate::api::DbState;
+use crate::db::{Role, User};
+use crate::error::SubmError;
+
+pub const SESSION_COOKIE: &str = "session";
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+pub fn new_session_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Session token from the cookie set by /api/login, or from an
+// `Authorization: Bearer` header for non-browser clients
+pub fn session_token(req: &HttpRequest) -> Option<String> {
+    if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+        return Some(cookie.value().to_string());
+    }
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// Any logged in user
+pub struct CurrentUser(pub User);
+
+/// A logged in user with the teacher role
+pub struct Teacher(pub User);
+
+impl FromRequest for CurrentUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let db = req.app_data::<web::Data<DbState>>().cloned();
+        let token = session_token(req);
+
+        Box::pin(async move {
+            let (Some(db), Some(token)) = (db, token) else {
+                return Err(SubmError::Unauthorized.into());
+            };
+
+            match db.get_session_user(&token).await? {
+                Some(user) => Ok(CurrentUser(user)),
+                None => Err(SubmError::Unauthorized.into()),
+            }
+        })
+    }
+}
+
+impl FromRequest for Teacher {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = CurrentUser::from_request(req, payload);
+
+        Box::pin(async move {
+            let CurrentUser(user) = user.await?;
+            if user.role != Role::Teacher {
+                return Err(SubmError::Forbidden.into());
+            }
+            Ok(Teacher(user))
+        })
+    }
+}
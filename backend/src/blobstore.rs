@@ -0,0 +1,337 @@
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+use aws_sdk_s3::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+/// Content of a blob being read out of a store
+pub type BlobReader = Pin<Box<dyn AsyncRead + Send>>;
+
+/// A blob as listed by its store
+#[derive(Debug)]
+pub struct BlobInfo {
+    pub hash: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Where uploaded files are kept, each under the sha256 of its content
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores a staged upload, returning whether the content is new to the
+    /// store. Content that is already there counts as modified just now, so
+    /// garbage collection leaves it to the submission about to reference it.
+    async fn put(&self, hash: &str, staged: NamedTempFile) -> io::Result<bool>;
+
+    /// Opens a blob for reading along with its size, `None` if it isn't stored
+    async fn get(&self, hash: &str) -> io::Result<Option<(u64, BlobReader)>>;
+
+    /// Opens `length` bytes of a blob, at least one, from `start` on, `None`
+    /// if it isn't stored
+    async fn get_range(&self, hash: &str, start: u64, length: u64) -> io::Result<Option<BlobReader>>;
+
+    /// Size of a blob, `None` if it isn't stored
+    async fn size(&self, hash: &str) -> io::Result<Option<u64>>;
+
+    async fn exists(&self, hash: &str) -> io::Result<bool>;
+
+    /// Removes a blob; removing one that isn't there is not an error
+    async fn delete(&self, hash: &str) -> io::Result<()>;
+
+    /// Every blob in the store
+    async fn list(&self) -> io::Result<Vec<BlobInfo>>;
+
+    /// Where the blob sits on the local filesystem, for stores that keep it there
+    fn local_path(&self, _hash: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Whether a name is one the store would give a blob
+pub fn is_blob_name(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Keeps blobs as files in a directory
+pub struct LocalStore {
+    dir: PathBuf,
+}
+
+impl LocalStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        LocalStore { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalStore {
+    async fn put(&self, hash: &str, staged: NamedTempFile) -> io::Result<bool> {
+        let path = self.dir.join(hash);
+        tokio::task::spawn_blocking(move || {
+            if path.is_file() {
+                // Same content is already stored, the staged copy is dropped
+                std::fs::File::options()
+                    .append(true)
+                    .open(&path)?
+                    .set_modified(SystemTime::now())?;
+                return Ok(false);
+            }
+            staged.persist(&path).map_err(|e| e.error)?;
+            Ok(true)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+
+    async fn get(&self, hash: &str) -> io::Result<Option<(u64, BlobReader)>> {
+        match tokio::fs::File::open(self.dir.join(hash)).await {
+            Ok(file) => {
+                let size = file.metadata().await?.len();
+                Ok(Some((size, Box::pin(file))))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_range(&self, hash: &str, start: u64, length: u64) -> io::Result<Option<BlobReader>> {
+        match tokio::fs::File::open(self.dir.join(hash)).await {
+            Ok(mut file) => {
+                file.seek(io::SeekFrom::Start(start)).await?;
+                Ok(Some(Box::pin(file.take(length))))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn size(&self, hash: &str) -> io::Result<Option<u64>> {
+        match tokio::fs::metadata(self.dir.join(hash)).await {
+            Ok(meta) => Ok(Some(meta.len())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn exists(&self, hash: &str) -> io::Result<bool> {
+        match tokio::fs::metadata(self.dir.join(hash)).await {
+            Ok(meta) => Ok(meta.is_file()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> io::Result<()> {
+        match tokio::fs::remove_file(self.dir.join(hash)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> io::Result<Vec<BlobInfo>> {
+        let mut blobs = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let meta = entry.metadata().await?;
+            // The staging area lives here too, and isn't part of the store
+            if meta.is_file() && is_blob_name(&name) {
+                blobs.push(BlobInfo {
+                    hash: name,
+                    size: meta.len(),
+                    modified: meta.modified()?,
+                });
+            }
+        }
+        Ok(blobs)
+    }
+
+    fn local_path(&self, hash: &str) -> Option<PathBuf> {
+        Some(self.dir.join(hash))
+    }
+}
+
+/// How to reach an S3-compatible bucket
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// Base URL of the service, e.g. `http://localhost:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Put in front of every key, so one bucket can hold several stores
+    pub prefix: String,
+}
+
+/// Keeps blobs as objects in an S3-compatible bucket, keyed by hash
+pub struct S3Store {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Store {
+    pub fn new(config: S3Config) -> Self {
+        let credentials =
+            Credentials::new(config.access_key, config.secret_key, None, None, "submission");
+        let s3_config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(config.region))
+            .endpoint_url(config.endpoint)
+            .credentials_provider(credentials)
+            // Self-hosted services rarely serve buckets as subdomains
+            .force_path_style(true)
+            .build();
+        S3Store {
+            client: Client::from_conf(s3_config),
+            bucket: config.bucket,
+            prefix: config.prefix,
+        }
+    }
+
+    fn key(&self, hash: &str) -> String {
+        format!("{}{}", self.prefix, hash)
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    async fn put(&self, hash: &str, staged: NamedTempFile) -> io::Result<bool> {
+        // Objects can't be touched, so known content is uploaded again to
+        // bring its time up to date
+        let new = !self.exists(hash).await?;
+        let body = ByteStream::from_path(staged.path())
+            .await
+            .map_err(io::Error::other)?;
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .body(body)
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(new)
+    }
+
+    async fn get(&self, hash: &str) -> io::Result<Option<(u64, BlobReader)>> {
+        let output = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .send()
+            .await
+        {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let size = output
+            .content_length()
+            .and_then(|n| u64::try_from(n).ok())
+            .ok_or_else(|| io::Error::other(format!("Object {} came without a length", hash)))?;
+        Ok(Some((size, Box::pin(output.body.into_async_read()))))
+    }
+
+    async fn get_range(&self, hash: &str, start: u64, length: u64) -> io::Result<Option<BlobReader>> {
+        let range = format!("bytes={}-{}", start, start + length - 1);
+        match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .range(range)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Some(Box::pin(output.body.into_async_read()))),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    async fn size(&self, hash: &str) -> io::Result<Option<u64>> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .send()
+            .await
+        {
+            Ok(output) => output
+                .content_length()
+                .and_then(|n| u64::try_from(n).ok())
+                .map(Some)
+                .ok_or_else(|| io::Error::other(format!("Object {} came without a length", hash))),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    async fn exists(&self, hash: &str) -> io::Result<bool> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(false),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> io::Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(hash))
+            .send()
+            .await
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    async fn list(&self) -> io::Result<Vec<BlobInfo>> {
+        let mut blobs = Vec::new();
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&self.prefix)
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(io::Error::other)?;
+            for object in page.contents() {
+                let Some(hash) = object.key().and_then(|key| key.strip_prefix(&self.prefix)) else {
+                    continue;
+                };
+                if !is_blob_name(hash) {
+                    continue;
+                }
+                blobs.push(BlobInfo {
+                    hash: hash.to_string(),
+                    size: object.size().and_then(|n| u64::try_from(n).ok()).unwrap_or(0),
+                    // Without a time the blob counts as brand new, so garbage
+                    // collection leaves it alone
+                    modified: object
+                        .last_modified()
+                        .and_then(|time| SystemTime::try_from(*time).ok())
+                        .unwrap_or_else(SystemTime::now),
+                });
+            }
+        }
+        Ok(blobs)
+    }
+}
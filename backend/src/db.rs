@@ -1,25 +1,234 @@
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, stream};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tempfile::NamedTempFile;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use turso::{Builder, Connection};
 
+use crate::auth::{hash_password, new_session_token, verify_password};
+use crate::blobstore::{BlobReader, BlobStore, LocalStore};
 use crate::error::SubmError;
+use crate::grading::{self, Verdict};
+use crate::migrations::{self, Migration};
+use crate::paths;
+use crate::pool::{ConnPool, PooledConn};
 
-use log::info;
+use log::{error, info};
+
+// Upper bound on connections kept around between requests
+const MAX_IDLE_CONNECTIONS: usize = 16;
+
+// Columns read by `problem_from_row`, in order, from `problem p`
+const PROBLEM_COLUMNS: &str =
+    "p.id, p.course, p.name, p.desc, p.max_files, p.max_file_size, p.max_total_size, p.allowed_extensions, \
+     p.deleted, p.revision, p.opens_at, p.soft_deadline, p.hard_deadline, p.max_score, p.created_at, p.updated_at";
+
+// Columns read by `submission_from_row`, in order, from `submission s` joined
+// with its `problem p`
+const SUBMISSION_COLUMNS: &str =
+    "s.id, s.comment, s.problem, s.author, s.revision, s.late, p.max_score, s.state, \
+     s.created_at, s.updated_at";
+
+// Columns read by `feedback_from_row`, in order, from `feedback f` joined
+// with its author `u`
+const FEEDBACK_COLUMNS: &str = "f.id, f.submission, f.grade, f.score, f.state, f.message, \
+     f.author, u.name, f.created_at, f.updated_at";
+
+// Subdirectory of the uploads directory holding files not yet committed
+const STAGING_DIR: &str = "tmp";
+
+// How much of a blob is read at a time when streaming it out
+const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long garbage collection leaves unreferenced uploads alone, so it doesn't
+/// race submissions that are still being stored
+pub const GC_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
 
 #[derive(Clone)]
 pub struct SubmDb {
-    conn: Connection,
+    pool: Arc<ConnPool>,
     uploads_dir: String,
+    store: Arc<dyn BlobStore>,
+    verify_blobs: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Course {
+    pub id: i64,
+    pub name: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Problem {
     pub id: i64,
+    pub course: i64,
+    pub name: String,
+    pub desc: String,
+    #[serde(flatten)]
+    pub limits: ProblemLimits,
+    #[serde(flatten)]
+    pub schedule: ProblemSchedule,
+    /// Retired, kept only because submissions refer to it
+    pub deleted: bool,
+    /// Current revision of the statement
+    pub revision: i64,
+    /// Most points a submission can score, the rubric's total if it has one
+    pub max_score: i64,
+    pub rubric: Vec<RubricCriterion>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// A named part of a problem's grading rubric
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RubricCriterion {
+    pub id: i64,
+    pub name: String,
+    pub points: i64,
+}
+
+/// A rubric criterion as given when creating or editing a problem
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NewCriterion {
+    pub name: String,
+    pub points: i64,
+}
+
+/// Points a feedback gives for one rubric criterion
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CriterionScore {
+    pub criterion: i64,
+    pub points: i64,
+}
+
+/// How the submissions to a problem went
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProblemStats {
+    pub problem: i64,
+    pub attempts: i64,
+    /// Whether any of the submissions was accepted
+    pub accepted: bool,
+}
+
+/// A problem's name and statement as they were at some point
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProblemRevision {
+    pub id: i64,
+    pub problem: i64,
+    /// Position in the problem's history, counting from 1
+    pub number: i64,
     pub name: String,
     pub desc: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Upload limits set on a problem, replacing the instance-wide ones where given
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProblemLimits {
+    pub max_files: Option<i64>,
+    pub max_file_size: Option<i64>,
+    pub max_total_size: Option<i64>,
+    pub allowed_extensions: Option<Vec<String>>,
+}
+
+impl ProblemLimits {
+    pub fn validate(&self) -> Result<(), SubmError> {
+        for (name, limit) in [
+            ("max_files", self.max_files),
+            ("max_file_size", self.max_file_size),
+            ("max_total_size", self.max_total_size),
+        ] {
+            if limit.is_some_and(|limit| limit < 1) {
+                return Err(SubmError::Validation(format!("{} must be at least 1", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// When a problem takes submissions. Past the soft deadline they are still
+/// accepted but marked late, past the hard deadline they are refused.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProblemSchedule {
+    pub opens_at: Option<DateTime<Utc>>,
+    pub soft_deadline: Option<DateTime<Utc>>,
+    pub hard_deadline: Option<DateTime<Utc>>,
+}
+
+impl ProblemSchedule {
+    pub fn validate(&self) -> Result<(), SubmError> {
+        let in_order = |earlier: Option<DateTime<Utc>>, later: Option<DateTime<Utc>>| {
+            match (earlier, later) {
+                (Some(earlier), Some(later)) => earlier <= later,
+                _ => true,
+            }
+        };
+        if !in_order(self.opens_at, self.soft_deadline)
+            || !in_order(self.opens_at, self.hard_deadline)
+            || !in_order(self.soft_deadline, self.hard_deadline)
+        {
+            return Err(SubmError::Validation(
+                "Problem must open before its soft deadline, and that must come before the hard one"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuses submissions made at `now` if the problem isn't open yet or its
+    /// hard deadline has passed
+    pub fn check_open(&self, now: DateTime<Utc>) -> Result<(), SubmError> {
+        if let Some(opens_at) = self.opens_at.filter(|&opens_at| now < opens_at) {
+            return Err(SubmError::Closed(format!("Problem opens at {}", opens_at.to_rfc3339())));
+        }
+        if let Some(deadline) = self.hard_deadline.filter(|&deadline| now > deadline) {
+            return Err(SubmError::Closed(format!(
+                "Deadline passed at {}",
+                deadline.to_rfc3339()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Whether a submission made at `now` counts as late
+    pub fn is_late(&self, now: DateTime<Utc>) -> bool {
+        self.soft_deadline.is_some_and(|deadline| now > deadline)
+    }
+
+    /// The schedule as it applies to a student given an extension until
+    /// `due_at`: that becomes their soft deadline, and the hard deadline is
+    /// pushed back so they can make use of it
+    pub fn with_extension(self, due_at: Option<DateTime<Utc>>) -> ProblemSchedule {
+        let Some(due_at) = due_at else {
+            return self;
+        };
+        ProblemSchedule {
+            opens_at: self.opens_at,
+            soft_deadline: Some(due_at),
+            hard_deadline: self.hard_deadline.map(|deadline| deadline.max(due_at)),
+        }
+    }
+}
+
+/// A personal due date for one student on one problem
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Extension {
+    pub problem: i64,
+    pub student: i64,
+    pub due_at: DateTime<Utc>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the due date was last replaced
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,379 +236,2367 @@ pub struct Submission {
     pub id: i64,
     pub comment: String,
     pub problem: i64,
+    pub author: Option<i64>,
+    /// Problem revision current at submit time, unknown for submissions
+    /// made before revisions were tracked
+    pub revision: Option<i64>,
+    /// Made after the problem's soft deadline, or the author's extension
+    pub late: bool,
     pub files: Vec<FileInfo>,
     pub status: SubmissionStatus,
+    pub created_at: Option<DateTime<Utc>>,
+    /// When a feedback last changed the submission's state, or when it was made
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// Upload content waiting in the staging area, deleted when dropped unless
+/// it was moved into the store
+pub struct StagedBlob {
+    file: NamedTempFile,
+    pub hash: String,
+    pub size: u64,
+}
+
+/// Writes an upload to the staging area chunk by chunk, hashing as it goes,
+/// so the whole file never has to be held in memory
+pub struct BlobWriter {
+    // Owns the staged file, removing it unless it is moved into the store
+    file: NamedTempFile,
+    // The same file, written without blocking the server's workers
+    writer: tokio::fs::File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl BlobWriter {
+    pub async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.hasher.update(chunk);
+        self.writer.write_all(chunk).await?;
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub async fn finish(mut self) -> std::io::Result<StagedBlob> {
+        self.writer.flush().await?;
+        self.writer.sync_all().await?;
+        Ok(StagedBlob {
+            file: self.file,
+            hash: format!("{:x}", self.hasher.finalize()),
+            size: self.size,
+        })
+    }
+}
+
+/// What a garbage collection run removed, or would remove on a dry run
+#[derive(Serialize, Debug, Default)]
+pub struct GcReport {
+    /// Hashes of stored blobs no `file` row points at
+    pub orphan_blobs: Vec<String>,
+    /// IDs of `file` rows no submission attaches
+    pub orphan_files: Vec<i64>,
+    /// Leftovers in the staging area from uploads that never finished
+    pub stale_staged: usize,
+    pub reclaimable_bytes: u64,
+}
+
+/// An `attachment` row pointing at a submission or file that doesn't exist
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DanglingAttachment {
+    pub submission: Option<i64>,
+    pub file: Option<i64>,
+}
+
+/// Everything wrong with the file store that an integrity check found
+#[derive(Serialize, Debug, Default)]
+pub struct FsckReport {
+    /// How many blobs were read and hashed
+    pub checked: usize,
+    /// Hashes `file` rows point at that have no blob
+    pub missing: Vec<String>,
+    /// Blobs whose content no longer hashes to their name
+    pub corrupt: Vec<String>,
+    /// IDs of `file` rows no submission attaches
+    pub unattached_files: Vec<i64>,
+    pub dangling_attachments: Vec<DanglingAttachment>,
+}
+
+impl FsckReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty()
+            && self.corrupt.is_empty()
+            && self.unattached_files.is_empty()
+            && self.dangling_attachments.is_empty()
+    }
+}
+
+// Where `read_blob` is in reading a blob
+struct BlobRead {
+    reader: BlobReader,
+    verify: bool,
+    hasher: Sha256,
+    held: Option<Bytes>,
+    expected: String,
+    done: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FileInfo {
     pub id: i64,
     pub name: String,
+    /// Where the file sits in the submission, `/` separated and ending in `name`
+    pub path: String,
     pub hash: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Feedback {
     pub id: i64,
+    pub submission: i64,
     pub grade: i64,
+    /// Total points, if the feedback scored the work
+    pub score: Option<i64>,
+    /// Breakdown of the score by rubric criterion
+    pub points: Vec<CriterionScore>,
+    /// State the feedback moved the submission into
+    pub state: SubmissionState,
     pub message: Option<String>,
+    /// Teacher who wrote it, unknown for feedback older than authorship
+    pub author: Option<i64>,
+    pub author_name: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the message was last edited, or when it was given
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+/// An earlier message of an edited feedback
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FeedbackEdit {
+    pub message: Option<String>,
+    /// When the edit replaced this message
+    pub replaced_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SubmissionStatus {
+    pub state: SubmissionState,
+    /// Whether `state` is accepted, kept for older clients
     pub accepted: bool,
+    /// Score from the latest feedback, if it gave one
+    pub score: Option<i64>,
+    pub max_score: i64,
     pub feedbacks: Vec<Feedback>,
 }
 
+impl SubmissionStatus {
+    /// Status in `state`, with the score taken from the latest of `feedbacks`,
+    /// which come newest first
+    pub fn new(state: SubmissionState, feedbacks: Vec<Feedback>, max_score: i64) -> SubmissionStatus {
+        SubmissionStatus {
+            state,
+            accepted: state == SubmissionState::Accepted,
+            score: feedbacks.first().and_then(|f| f.score),
+            max_score,
+            feedbacks,
+        }
+    }
+}
+
+/// Verdict on a submission. Every submission starts out pending and each
+/// feedback moves it to another state. No feedback puts it back to pending,
+/// but retracting every feedback it got does.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SubmissionState {
+    Pending,
+    UnderReview,
+    Accepted,
+    Rejected,
+    NeedsRevision,
+}
+
+impl SubmissionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubmissionState::Pending => "pending",
+            SubmissionState::UnderReview => "under_review",
+            SubmissionState::Accepted => "accepted",
+            SubmissionState::Rejected => "rejected",
+            SubmissionState::NeedsRevision => "needs_revision",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<SubmissionState> {
+        match s {
+            "pending" => Some(SubmissionState::Pending),
+            "under_review" => Some(SubmissionState::UnderReview),
+            "accepted" => Some(SubmissionState::Accepted),
+            "rejected" => Some(SubmissionState::Rejected),
+            "needs_revision" => Some(SubmissionState::NeedsRevision),
+            _ => None,
+        }
+    }
+
+    /// Whether a feedback may move a submission from this state to `next`.
+    /// A review can start before a verdict or to reconsider one, and a verdict
+    /// can always be revised. Only retractions lead back to pending, see
+    /// [`SubmDb::retract_feedback`].
+    pub fn can_become(&self, next: SubmissionState) -> bool {
+        match next {
+            SubmissionState::Pending => false,
+            SubmissionState::UnderReview => *self != SubmissionState::UnderReview,
+            SubmissionState::Accepted
+            | SubmissionState::Rejected
+            | SubmissionState::NeedsRevision => true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Teacher,
+    Student,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Teacher => "teacher",
+            Role::Student => "student",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Role> {
+        match s {
+            "teacher" => Some(Role::Teacher),
+            "student" => Some(Role::Student),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub role: Role,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl User {
+    /// Author to restrict submission lookups to, `None` meaning everyone's
+    pub fn submission_filter(&self) -> Option<i64> {
+        match self.role {
+            Role::Teacher => None,
+            Role::Student => Some(self.id),
+        }
+    }
+
+    /// Member to restrict course and problem lookups to, `None` meaning all courses
+    pub fn course_filter(&self) -> Option<i64> {
+        match self.role {
+            Role::Teacher => None,
+            Role::Student => Some(self.id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateUser {
+    pub name: String,
+    pub password: String,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct Login {
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Deserialize)]
+pub struct CreateCourse {
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct AddCourseMember {
+    pub user: i64,
+}
+
 #[derive(Deserialize)]
 pub struct CreateProblem {
+    pub course: i64,
+    pub name: String,
+    pub desc: String,
+    #[serde(flatten, default)]
+    pub limits: ProblemLimits,
+    #[serde(flatten, default)]
+    pub schedule: ProblemSchedule,
+    /// Defaults to the rubric's total, or to 1 without a rubric
+    #[serde(default)]
+    pub max_score: Option<i64>,
+    #[serde(default)]
+    pub rubric: Vec<NewCriterion>,
+}
+
+/// Full replacement of a problem's statement and limits
+#[derive(Deserialize)]
+pub struct UpdateProblem {
     pub name: String,
     pub desc: String,
+    #[serde(flatten, default)]
+    pub limits: ProblemLimits,
+    #[serde(flatten, default)]
+    pub schedule: ProblemSchedule,
+    /// Defaults to the rubric's total, or to 1 without a rubric
+    #[serde(default)]
+    pub max_score: Option<i64>,
+    #[serde(default)]
+    pub rubric: Vec<NewCriterion>,
+}
+
+/// Changes to a problem, fields left out stay as they are. A limit given as
+/// `null` is cleared, so the instance-wide one applies again; the same goes
+/// for dates, which are then no longer enforced.
+#[derive(Deserialize, Default)]
+pub struct PatchProblem {
+    pub name: Option<String>,
+    pub desc: Option<String>,
+    #[serde(default, deserialize_with = "present")]
+    pub max_files: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    pub max_file_size: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    pub max_total_size: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    pub allowed_extensions: Option<Option<Vec<String>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub opens_at: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub soft_deadline: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "present")]
+    pub hard_deadline: Option<Option<DateTime<Utc>>>,
+    pub max_score: Option<i64>,
+    pub rubric: Option<Vec<NewCriterion>>,
+}
+
+// Tells a field set to `null` (`Some(None)`) apart from a missing one (`None`)
+fn present<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl PatchProblem {
+    pub fn apply(self, problem: Problem) -> UpdateProblem {
+        let limits = problem.limits;
+        let schedule = problem.schedule;
+        // A new rubric brings its own total unless one is given
+        let max_score = match (self.max_score, &self.rubric) {
+            (Some(max_score), _) => Some(max_score),
+            (None, Some(_)) => None,
+            (None, None) => Some(problem.max_score),
+        };
+        let rubric = self.rubric.unwrap_or_else(|| {
+            problem
+                .rubric
+                .into_iter()
+                .map(|criterion| NewCriterion {
+                    name: criterion.name,
+                    points: criterion.points,
+                })
+                .collect()
+        });
+        UpdateProblem {
+            name: self.name.unwrap_or(problem.name),
+            desc: self.desc.unwrap_or(problem.desc),
+            limits: ProblemLimits {
+                max_files: self.max_files.unwrap_or(limits.max_files),
+                max_file_size: self.max_file_size.unwrap_or(limits.max_file_size),
+                max_total_size: self.max_total_size.unwrap_or(limits.max_total_size),
+                allowed_extensions: self
+                    .allowed_extensions
+                    .unwrap_or(limits.allowed_extensions),
+            },
+            schedule: ProblemSchedule {
+                opens_at: self.opens_at.unwrap_or(schedule.opens_at),
+                soft_deadline: self.soft_deadline.unwrap_or(schedule.soft_deadline),
+                hard_deadline: self.hard_deadline.unwrap_or(schedule.hard_deadline),
+            },
+            max_score,
+            rubric,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 pub struct CreateSubmission {
     pub comment: String,
+    /// Whether it came in past the author's soft deadline, see
+    /// [`ProblemSchedule::is_late`]
+    #[serde(default)]
+    pub late: bool,
 }
 
+#[derive(Deserialize)]
+pub struct GrantExtension {
+    pub due_at: DateTime<Utc>,
+}
+
+/// Bounds on when listed items were created: `since` inclusive, `until`
+/// exclusive. Items from before timestamps were recorded never match a bound.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+pub struct TimeRange {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    pub fn validate(&self) -> Result<(), SubmError> {
+        match (self.since, self.until) {
+            (Some(since), Some(until)) if since > until => Err(SubmError::Validation(
+                "since must not be after until".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    // Bounds in the unix seconds they are stored as
+    fn bounds(&self) -> (Option<i64>, Option<i64>) {
+        (
+            self.since.map(|since| since.timestamp()),
+            self.until.map(|until| until.timestamp()),
+        )
+    }
+}
+
+/// Largest page a listing hands out at once
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Order of listings, by when the items were created
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl SortOrder {
+    // IDs are handed out in creation order, so ordering by them is
+    // chronological and gives cursors a stable key
+    fn sql(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "DESC",
+            SortOrder::Oldest => "ASC",
+        }
+    }
+
+    // Comparison selecting the IDs that come after a cursor
+    fn after(&self) -> &'static str {
+        match self {
+            SortOrder::Newest => "<",
+            SortOrder::Oldest => ">",
+        }
+    }
+}
+
+/// One page of a listing, with the cursor of the next one if there's more
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<i64>,
+}
+
+impl<T> Page<T> {
+    // Trims the one extra row fetched to tell whether another page follows
+    fn from_rows(mut items: Vec<T>, limit: Option<i64>, id: impl Fn(&T) -> i64) -> Page<T> {
+        let next_cursor = match limit {
+            Some(limit) if items.len() as i64 > limit => {
+                items.truncate(limit as usize);
+                items.last().map(id)
+            }
+            _ => None,
+        };
+        Page { items, next_cursor }
+    }
+}
+
+fn validate_limit(limit: Option<i64>) -> Result<(), SubmError> {
+    match limit {
+        Some(limit) if !(1..=MAX_PAGE_SIZE).contains(&limit) => Err(SubmError::Validation(
+            format!("limit must be between 1 and {}", MAX_PAGE_SIZE),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// `LIMIT` clause fetching one row past the page, or nothing for no limit
+fn limit_clause(limit: Option<i64>) -> String {
+    limit.map_or(String::new(), |limit| format!("LIMIT {}", limit + 1))
+}
+
+/// Which submissions to list. Without a `limit` everything matching is
+/// listed at once; with one, `cursor` picks up where the previous page ended.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct SubmissionQuery {
+    pub course: Option<i64>,
+    pub problem: Option<i64>,
+    pub student: Option<i64>,
+    pub status: Option<SubmissionState>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub cursor: Option<i64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl SubmissionQuery {
+    pub fn validate(&self) -> Result<(), SubmError> {
+        self.range().validate()?;
+        validate_limit(self.limit)
+    }
+
+    fn range(&self) -> TimeRange {
+        TimeRange {
+            since: self.since,
+            until: self.until,
+        }
+    }
+}
+
+/// Which problems to list, paged like submissions
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ProblemQuery {
+    pub course: Option<i64>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+    pub cursor: Option<i64>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl ProblemQuery {
+    pub fn validate(&self) -> Result<(), SubmError> {
+        self.range().validate()?;
+        validate_limit(self.limit)
+    }
+
+    fn range(&self) -> TimeRange {
+        TimeRange {
+            since: self.since,
+            until: self.until,
+        }
+    }
+}
+
+/// New message for an existing feedback. Its verdict can't change, a new
+/// feedback records a change of mind instead.
+#[derive(Deserialize)]
+pub struct EditFeedback {
+    pub message: Option<String>,
+}
+
+/// Feedback on a submission. It needs a state, a grade or a score. Without a
+/// state, grade 1 or else a full score accepts the submission and anything
+/// less rejects it.
 #[derive(Deserialize)]
 pub struct CreateFeedback {
-    pub grade: i64,
+    #[serde(default)]
+    pub state: Option<SubmissionState>,
+    pub grade: Option<i64>,
+    #[serde(default)]
+    pub score: Option<i64>,
+    #[serde(default)]
+    pub points: Vec<CriterionScore>,
     pub message: Option<String>,
 }
 
+// IDs for an `IN (...)` list. They come from the database as integers, so
+// they are safe to put in the query, and a literal list has no parameter limit.
+fn id_list(ids: impl IntoIterator<Item = i64>) -> String {
+    ids.into_iter().map(|id| id.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+fn parse_state(state: &str) -> Result<SubmissionState, SubmError> {
+    SubmissionState::parse(state)
+        .ok_or_else(|| SubmError::Internal(format!("Unknown submission state {}", state)))
+}
+
+async fn hash_blob(mut reader: BlobReader) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; BLOB_CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(format!("{:x}", hasher.finalize()));
+        }
+        hasher.update(&chunk[..n]);
+    }
+}
+
+fn missing_blob(hash: &str) -> SubmError {
+    SubmError::Storage(std::io::Error::new(
+        std::io::ErrorKind::NotFound,
+        format!("Blob {} is missing", hash),
+    ))
+}
+
+// Reads a blob in chunks, checking it against `expected` if given
+fn read_blob(
+    reader: BlobReader,
+    expected: Option<String>,
+) -> impl Stream<Item = std::io::Result<Bytes>> + use<> {
+    let state = BlobRead {
+        reader,
+        verify: expected.is_some(),
+        hasher: Sha256::new(),
+        held: None,
+        expected: expected.unwrap_or_default(),
+        done: false,
+    };
+    stream::unfold(state, |mut state| async move {
+        while !state.done {
+            let mut chunk = vec![0; BLOB_CHUNK_SIZE];
+            let n = match state.reader.read(&mut chunk).await {
+                Ok(n) => n,
+                Err(e) => {
+                    state.done = true;
+                    state.held = None;
+                    return Some((Err(e), state));
+                }
+            };
+
+            if n == 0 {
+                state.done = true;
+                // Only a blob that checks out gets its last chunk sent
+                let last = state.held.take();
+                let actual = format!("{:x}", state.hasher.finalize_reset());
+                if state.verify && actual != state.expected {
+                    error!("Blob {} is corrupt, its content hashes to {}", state.expected, actual);
+                    let e = std::io::Error::new(std::io::ErrorKind::InvalidData, "Blob is corrupt");
+                    return Some((Err(e), state));
+                }
+                return last.map(|last| (Ok(last), state));
+            }
+
+            chunk.truncate(n);
+            if !state.verify {
+                return Some((Ok(Bytes::from(chunk)), state));
+            }
+            state.hasher.update(&chunk);
+            if let Some(previous) = state.held.replace(Bytes::from(chunk)) {
+                return Some((Ok(previous), state));
+            }
+        }
+        None
+    })
+}
+
+fn from_timestamp(secs: Option<i64>) -> Option<DateTime<Utc>> {
+    secs.and_then(|secs| DateTime::from_timestamp(secs, 0))
+}
+
 impl SubmDb {
     pub async fn new(s: &str, uploads_dir: String) -> Self {
         let db = Builder::new_local(s).build().await.unwrap();
 
-        // Create uploads directory if it doesn't exist
-        if !Path::new(&uploads_dir).exists() {
-            fs::create_dir_all(&uploads_dir).expect("Failed to create uploads directory");
+        // Create uploads and staging directories if they don't exist
+        let staging_dir = Path::new(&uploads_dir).join(STAGING_DIR);
+        if !staging_dir.exists() {
+            fs::create_dir_all(&staging_dir).expect("Failed to create uploads directory");
+        }
+
+        SubmDb {
+            pool: Arc::new(ConnPool::new(db, MAX_IDLE_CONNECTIONS)),
+            store: Arc::new(LocalStore::new(&uploads_dir)),
+            uploads_dir,
+            verify_blobs: false,
+        }
+    }
+
+    /// Keeps uploads in `store` instead of the uploads directory, which is
+    /// then only used for staging
+    pub fn with_blob_store(mut self, store: Arc<dyn BlobStore>) -> Self {
+        self.store = store;
+        self
+    }
+
+    /// Makes downloads check each blob against its hash as it is read
+    pub fn with_blob_verification(mut self, verify: bool) -> Self {
+        self.verify_blobs = verify;
+        self
+    }
+
+    pub fn verifies_blobs(&self) -> bool {
+        self.verify_blobs
+    }
+
+    fn conn(&self) -> Result<PooledConn<'_>, turso::Error> {
+        self.pool.get()
+    }
+
+    /// Brings the schema up to date, panicking if that fails
+    pub async fn init(&self) {
+        let applied = self.migrate().await.unwrap();
+        if !applied.is_empty() {
+            info!("Applied {} migrations", applied.len());
+        }
+    }
+
+    pub async fn migrate(&self) -> Result<Vec<&'static Migration>, SubmError> {
+        let mut db = self.conn()?;
+        db.write().await?;
+        Ok(migrations::migrate(&db).await?)
+    }
+
+    pub async fn schema_version(&self) -> Result<i64, SubmError> {
+        let db = self.conn()?;
+        Ok(migrations::current_version(&db).await?)
+    }
+
+    pub async fn create_user(&self, user: CreateUser) -> Result<i64, SubmError> {
+        let password_hash =
+            hash_password(&user.password).map_err(|e| SubmError::Internal(e.to_string()))?;
+
+        let mut db = self.conn()?;
+        db.write().await?;
+        let now = Utc::now().timestamp();
+        let mut existing = db
+            .query("SELECT 1 FROM user WHERE name = ?", [user.name.as_str()])
+            .await?;
+        if existing.next().await?.is_some() {
+            return Err(SubmError::Conflict(format!("User {} already exists", user.name)));
+        }
+        drop(existing);
+
+        db.execute(
+                "INSERT INTO user (name, password_hash, role, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                (
+                    user.name.as_str(),
+                    password_hash.as_str(),
+                    user.role.as_str(),
+                    now,
+                    now,
+                ),
+            )
+            .await?;
+
+        let id = db.last_insert_rowid();
+        Ok(id)
+    }
+
+    /// Checks the credentials and opens a new session, returning its token
+    pub async fn login(
+        &self,
+        login: Login,
+    ) -> Result<Option<(String, User)>, SubmError> {
+        let mut db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT id, name, role, created_at, updated_at, password_hash FROM user WHERE name = ?",
+                [login.name.as_str()],
+            )
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let password_hash: String = row.get(5)?;
+        if !verify_password(&login.password, &password_hash) {
+            return Ok(None);
+        }
+        let user = Self::user_from_row(&row)?;
+        drop(rows);
+
+        let token = new_session_token();
+        let now = Utc::now().timestamp();
+        db.write().await?;
+        db.execute(
+                "INSERT INTO session (token, user, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                (token.as_str(), user.id, now, now),
+            )
+            .await?;
+
+        Ok(Some((token, user)))
+    }
+
+    pub async fn logout(&self, token: &str) -> Result<(), SubmError> {
+        let mut db = self.conn()?;
+        db.write().await?;
+        db.execute("DELETE FROM session WHERE token = ?", [token])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_session_user(
+        &self,
+        token: &str,
+    ) -> Result<Option<User>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT u.id, u.name, u.role, u.created_at, u.updated_at
+                 FROM user u JOIN session s ON u.id = s.user WHERE s.token = ?",
+                [token],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(Some(Self::user_from_row(&row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn user_from_row(row: &turso::Row) -> Result<User, SubmError> {
+        let role: String = row.get(2)?;
+        Ok(User {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            role: Role::parse(&role)
+                .ok_or_else(|| SubmError::Internal(format!("Unknown user role {}", role)))?,
+            created_at: from_timestamp(row.get(3)?),
+            updated_at: from_timestamp(row.get(4)?),
+        })
+    }
+
+    pub async fn get_user_by_id(&self, id: i64) -> Result<Option<User>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT id, name, role, created_at, updated_at FROM user WHERE id = ?",
+                [id],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(Some(Self::user_from_row(&row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Creates a course with its creator as the first member
+    pub async fn create_course(
+        &self,
+        course: CreateCourse,
+        creator: i64,
+    ) -> Result<i64, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::insert_course(&db, course, creator).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn insert_course(db: &Connection, course: CreateCourse, creator: i64) -> Result<i64, SubmError> {
+        let now = Utc::now().timestamp();
+        db.execute(
+                "INSERT INTO course (name, created_at, updated_at) VALUES (?, ?, ?)",
+                (course.name.as_str(), now, now),
+            )
+            .await?;
+
+        let id = db.last_insert_rowid();
+        Self::insert_course_member(db, id, creator).await?;
+        Ok(id)
+    }
+
+    /// Lists courses created within `range`, only those `member` belongs to if given
+    pub async fn get_courses(
+        &self,
+        member: Option<i64>,
+        range: &TimeRange,
+    ) -> Result<Vec<Course>, SubmError> {
+        let db = self.conn()?;
+        let (since, until) = range.bounds();
+        let mut rows = db
+            .query(
+                "SELECT c.id, c.name, c.created_at, c.updated_at FROM course c
+                 LEFT JOIN course_member m ON m.course = c.id AND m.user = ?
+                 WHERE (? IS NULL OR m.user IS NOT NULL)
+                 AND (? IS NULL OR c.created_at >= ?)
+                 AND (? IS NULL OR c.created_at < ?)
+                 ORDER BY c.created_at DESC, c.id DESC",
+                [member, member, since, since, until, until],
+            )
+            .await?;
+
+        let mut courses = Vec::new();
+        while let Some(row) = rows.next().await? {
+            courses.push(Self::course_from_row(&row)?);
+        }
+        Ok(courses)
+    }
+
+    pub async fn get_course_by_id(
+        &self,
+        id: i64,
+        member: Option<i64>,
+    ) -> Result<Option<Course>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT c.id, c.name, c.created_at, c.updated_at FROM course c
+                 LEFT JOIN course_member m ON m.course = c.id AND m.user = ?
+                 WHERE c.id = ? AND (? IS NULL OR m.user IS NOT NULL)",
+                (member, id, member),
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            Ok(Some(Self::course_from_row(&row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn course_from_row(row: &turso::Row) -> Result<Course, SubmError> {
+        Ok(Course {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            created_at: from_timestamp(row.get(2)?),
+            updated_at: from_timestamp(row.get(3)?),
+        })
+    }
+
+    /// Adds `user` to `course`; adding an existing member changes nothing
+    pub async fn add_course_member(
+        &self,
+        course: i64,
+        user: i64,
+    ) -> Result<(), SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::insert_course_member(&db, course, user).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn insert_course_member(db: &Connection, course: i64, user: i64) -> Result<(), SubmError> {
+        let mut rows = db
+            .query(
+                "SELECT 1 FROM course_member WHERE course = ? AND user = ?",
+                [course, user],
+            )
+            .await?;
+        if rows.next().await?.is_some() {
+            return Ok(());
+        }
+        drop(rows);
+
+        let now = Utc::now().timestamp();
+        db.execute(
+                "INSERT INTO course_member (course, user, created_at, updated_at) VALUES (?, ?, ?, ?)",
+                [course, user, now, now],
+            )
+            .await?;
+        db.execute(
+                "UPDATE course SET updated_at = ? WHERE id = ?",
+                [now, course],
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_course_members(
+        &self,
+        course: i64,
+    ) -> Result<Vec<User>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT u.id, u.name, u.role, u.created_at, u.updated_at FROM user u
+                 JOIN course_member m ON u.id = m.user
+                 WHERE m.course = ? ORDER BY u.name",
+                [course],
+            )
+            .await?;
+
+        let mut members = Vec::new();
+        while let Some(row) = rows.next().await? {
+            members.push(Self::user_from_row(&row)?);
+        }
+        Ok(members)
+    }
+
+    pub async fn create_problem(
+        &self,
+        problem: CreateProblem,
+    ) -> Result<i64, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::insert_problem(&db, problem).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn insert_problem(db: &Connection, problem: CreateProblem) -> Result<i64, SubmError> {
+        problem.schedule.validate()?;
+        problem.limits.validate()?;
+        let limits = problem.limits;
+        let allowed_extensions = limits.allowed_extensions.map(|exts| exts.join(","));
+
+        let now = Utc::now().timestamp();
+        db.execute(
+                "INSERT INTO problem (course, name, desc, max_files, max_file_size, max_total_size, allowed_extensions, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                (
+                    problem.course,
+                    problem.name.as_str(),
+                    problem.desc.as_str(),
+                    limits.max_files,
+                    limits.max_file_size,
+                    limits.max_total_size,
+                    allowed_extensions,
+                    now,
+                    now,
+                ),
+            )
+            .await?;
+
+        let id = db.last_insert_rowid();
+        Self::set_schedule(db, id, &problem.schedule).await?;
+        Self::set_grading(db, id, problem.max_score, &problem.rubric).await?;
+        Self::add_revision(db, id, &problem.name, &problem.desc).await?;
+        Ok(id)
+    }
+
+    /// Replaces the statement and limits of a problem that isn't deleted,
+    /// returning whether there was one. Changes to the name or statement are
+    /// recorded as a new revision.
+    pub async fn update_problem(
+        &self,
+        id: i64,
+        problem: UpdateProblem,
+    ) -> Result<bool, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::apply_problem_update(&db, id, problem).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn apply_problem_update(
+        db: &Connection,
+        id: i64,
+        problem: UpdateProblem,
+    ) -> Result<bool, SubmError> {
+        problem.schedule.validate()?;
+        problem.limits.validate()?;
+        let (old_name, old_desc): (String, String) = {
+            let mut rows = db
+                .query("SELECT name, desc FROM problem WHERE id = ? AND deleted = 0", [id])
+                .await?;
+            match rows.next().await? {
+                Some(row) => (row.get(0)?, row.get(1)?),
+                None => return Ok(false),
+            }
+        };
+
+        let limits = problem.limits;
+        let allowed_extensions = limits.allowed_extensions.map(|exts| exts.join(","));
+        db.execute(
+                "UPDATE problem
+                 SET name = ?, desc = ?, max_files = ?, max_file_size = ?, max_total_size = ?, allowed_extensions = ?
+                 WHERE id = ?",
+                (
+                    problem.name.as_str(),
+                    problem.desc.as_str(),
+                    limits.max_files,
+                    limits.max_file_size,
+                    limits.max_total_size,
+                    allowed_extensions,
+                    id,
+                ),
+            )
+            .await?;
+        db.execute(
+                "UPDATE problem SET updated_at = ? WHERE id = ?",
+                [Utc::now().timestamp(), id],
+            )
+            .await?;
+        Self::set_schedule(db, id, &problem.schedule).await?;
+        Self::set_grading(db, id, problem.max_score, &problem.rubric).await?;
+
+        if problem.name != old_name || problem.desc != old_desc {
+            Self::add_revision(db, id, &problem.name, &problem.desc).await?;
+        }
+        Ok(true)
+    }
+
+    async fn set_schedule(
+        db: &Connection,
+        problem_id: i64,
+        schedule: &ProblemSchedule,
+    ) -> Result<(), SubmError> {
+        db.execute(
+                "UPDATE problem SET opens_at = ?, soft_deadline = ?, hard_deadline = ? WHERE id = ?",
+                (
+                    schedule.opens_at.map(|t| t.timestamp()),
+                    schedule.soft_deadline.map(|t| t.timestamp()),
+                    schedule.hard_deadline.map(|t| t.timestamp()),
+                    problem_id,
+                ),
+            )
+            .await?;
+        Ok(())
+    }
+
+    // Replaces the rubric unless it is unchanged, which is only allowed while
+    // no feedback has scored against it
+    async fn set_grading(
+        db: &Connection,
+        problem_id: i64,
+        max_score: Option<i64>,
+        rubric: &[NewCriterion],
+    ) -> Result<(), SubmError> {
+        let max_score = grading::resolve_max_score(max_score, rubric)?;
+        db.execute(
+                "UPDATE problem SET max_score = ? WHERE id = ?",
+                [max_score, problem_id],
+            )
+            .await?;
+
+        let current: Vec<NewCriterion> = Self::get_rubric(db, problem_id)
+            .await?
+            .into_iter()
+            .map(|criterion| NewCriterion {
+                name: criterion.name,
+                points: criterion.points,
+            })
+            .collect();
+        if current == rubric {
+            return Ok(());
+        }
+
+        let mut used = db
+            .query(
+                "SELECT 1 FROM feedback_points fp
+                 JOIN rubric_criterion c ON c.id = fp.criterion
+                 WHERE c.problem = ? LIMIT 1",
+                [problem_id],
+            )
+            .await?;
+        if used.next().await?.is_some() {
+            return Err(SubmError::Conflict(
+                "Rubric can't change once feedback has been scored with it".to_string(),
+            ));
+        }
+
+        db.execute("DELETE FROM rubric_criterion WHERE problem = ?", [problem_id])
+            .await?;
+        let now = Utc::now().timestamp();
+        for (position, criterion) in rubric.iter().enumerate() {
+            db.execute(
+                    "INSERT INTO rubric_criterion (problem, position, name, points, created_at, updated_at)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    (problem_id, position as i64, criterion.name.as_str(), criterion.points, now, now),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn get_rubric(db: &Connection, problem_id: i64) -> Result<Vec<RubricCriterion>, SubmError> {
+        let mut rows = db
+            .query(
+                "SELECT id, name, points FROM rubric_criterion WHERE problem = ? ORDER BY position",
+                [problem_id],
+            )
+            .await?;
+
+        let mut rubric = Vec::new();
+        while let Some(row) = rows.next().await? {
+            rubric.push(RubricCriterion {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                points: row.get(2)?,
+            });
+        }
+        Ok(rubric)
+    }
+
+    // Records the statement as the problem's newest revision
+    async fn add_revision(
+        db: &Connection,
+        problem_id: i64,
+        name: &str,
+        desc: &str,
+    ) -> Result<i64, SubmError> {
+        let mut rows = db
+            .query(
+                "SELECT COALESCE(MAX(number), 0) + 1 FROM problem_revision WHERE problem = ?",
+                [problem_id],
+            )
+            .await?;
+        let number: i64 = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => 1,
+        };
+        drop(rows);
+
+        let now = Utc::now().timestamp();
+        db.execute(
+                "INSERT INTO problem_revision (problem, number, name, desc, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?)",
+                (problem_id, number, name, desc, now, now),
+            )
+            .await?;
+
+        let revision_id = db.last_insert_rowid();
+        db.execute(
+                "UPDATE problem SET revision = ? WHERE id = ?",
+                [revision_id, problem_id],
+            )
+            .await?;
+        Ok(revision_id)
+    }
+
+    /// Commits the transaction open on `db` if `result` is a success, and
+    /// rolls it back otherwise. Either way the next writer can go on.
+    async fn finish_transaction<T>(
+        db: &mut PooledConn<'_>,
+        result: Result<T, SubmError>,
+    ) -> Result<T, SubmError> {
+        let result = match result {
+            Ok(value) => db.execute("COMMIT", ()).await.map(|_| value).map_err(Into::into),
+            Err(e) => Err(e),
+        };
+        if result.is_err()
+            && let Err(e) = db.execute("ROLLBACK", ()).await
+        {
+            error!("Rolling back failed: {}", e);
+        }
+        db.end_write();
+        result
+    }
+
+    /// Gives a student a personal due date, replacing any they already had
+    pub async fn grant_extension(
+        &self,
+        problem_id: i64,
+        student: i64,
+        extension: GrantExtension,
+    ) -> Result<Extension, SubmError> {
+        let mut db = self.conn()?;
+        let due_at = extension.due_at.timestamp();
+
+        db.begin().await?;
+        let result = Self::store_extension(&db, problem_id, student, due_at).await;
+        Self::finish_transaction(&mut db, result).await?;
+        drop(db);
+
+        self.get_extensions(problem_id, Some(student))
+            .await?
+            .pop()
+            .ok_or_else(|| SubmError::Internal("Granted extension went missing".to_string()))
+    }
+
+    async fn store_extension(
+        db: &Connection,
+        problem_id: i64,
+        student: i64,
+        due_at: i64,
+    ) -> Result<(), SubmError> {
+        let now = Utc::now().timestamp();
+        let replaced = db
+            .execute(
+                "UPDATE extension SET due_at = ?, updated_at = ? WHERE problem = ? AND student = ?",
+                [due_at, now, problem_id, student],
+            )
+            .await?;
+        if replaced == 0 {
+            db.execute(
+                    "INSERT INTO extension (problem, student, due_at, created_at, updated_at) VALUES (?, ?, ?, ?, ?)",
+                    [problem_id, student, due_at, now, now],
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Takes back a student's extension, returning whether they had one
+    pub async fn revoke_extension(&self, problem_id: i64, student: i64) -> Result<bool, SubmError> {
+        let mut db = self.conn()?;
+        db.write().await?;
+        let removed = db
+            .execute(
+                "DELETE FROM extension WHERE problem = ? AND student = ?",
+                [problem_id, student],
+            )
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Extensions on a problem, only the one of `student` if given
+    pub async fn get_extensions(
+        &self,
+        problem_id: i64,
+        student: Option<i64>,
+    ) -> Result<Vec<Extension>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT problem, student, due_at, created_at, updated_at FROM extension
+                 WHERE problem = ? AND (? IS NULL OR student = ?)
+                 ORDER BY student",
+                (problem_id, student, student),
+            )
+            .await?;
+
+        let mut extensions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let due_at: i64 = row.get(2)?;
+            extensions.push(Extension {
+                problem: row.get(0)?,
+                student: row.get(1)?,
+                due_at: from_timestamp(Some(due_at))
+                    .ok_or_else(|| SubmError::Internal(format!("Bad extension date {}", due_at)))?,
+                created_at: from_timestamp(row.get(3)?),
+                updated_at: from_timestamp(row.get(4)?),
+            });
+        }
+        Ok(extensions)
+    }
+
+    /// Submission counts of every problem that has submissions, only
+    /// counting those of `author` if given
+    pub async fn get_problem_stats(&self, author: Option<i64>) -> Result<Vec<ProblemStats>, SubmError> {
+        let db = self.conn()?;
+        // Turso takes no parameters inside aggregates; the state is one of a
+        // fixed set of names, safe to put in the query
+        let sql = format!(
+            "SELECT problem, COUNT(*), MAX(state = '{}') FROM submission
+             WHERE (? IS NULL OR author = ?)
+             GROUP BY problem ORDER BY problem",
+            SubmissionState::Accepted.as_str()
+        );
+        let mut rows = db.query(&sql, [author, author]).await?;
+
+        let mut stats = Vec::new();
+        while let Some(row) = rows.next().await? {
+            let accepted: i64 = row.get(2)?;
+            stats.push(ProblemStats {
+                problem: row.get(0)?,
+                attempts: row.get(1)?,
+                accepted: accepted != 0,
+            });
         }
+        Ok(stats)
+    }
 
-        let conn = db.connect().unwrap();
+    /// Every revision of a problem, oldest first
+    pub async fn get_problem_revisions(
+        &self,
+        problem_id: i64,
+    ) -> Result<Vec<ProblemRevision>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT id, problem, number, name, desc, created_at, updated_at FROM problem_revision
+                 WHERE problem = ? ORDER BY number",
+                [problem_id],
+            )
+            .await?;
 
-        SubmDb {
-            conn: conn,
-            uploads_dir: uploads_dir,
+        let mut revisions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            revisions.push(ProblemRevision {
+                id: row.get(0)?,
+                problem: row.get(1)?,
+                number: row.get(2)?,
+                name: row.get(3)?,
+                desc: row.get(4)?,
+                created_at: from_timestamp(row.get(5)?),
+                updated_at: from_timestamp(row.get(6)?),
+            });
         }
+        Ok(revisions)
     }
 
-    pub async fn init(&self) {
-        let db = &self.conn;
-        db.execute_batch(
-                "
-              CREATE TABLE IF NOT EXISTS problem (
-                  id INTEGER PRIMARY KEY,
-                  name TEXT,
-                  desc TEXT
-              );
-              CREATE TABLE IF NOT EXISTS file (
-                  id INTEGER PRIMARY KEY,
-                  name TEXT,
-                  hash TEXT
-              );
-              CREATE TABLE IF NOT EXISTS submission (
-                  id INTEGER PRIMARY KEY,
-                  comment TEXT,
-                  problem INTEGER,
-                  FOREIGN KEY (problem) REFERENCES problem(id)
-              );
-              CREATE TABLE IF NOT EXISTS attachment (
-                  submission INTEGER,
-                  file INTEGER,
-                  FOREIGN KEY (submission) REFERENCES submission(id),
-                  FOREIGN KEY (file) REFERENCES file(id)
-              );
-              CREATE TABLE IF NOT EXISTS feedback (
-                  id INTEGER PRIMARY KEY,
-                  submission INTEGER,
-                  grade INTEGER CHECK (grade IN (0, 1)),
-                  message TEXT,
-                  FOREIGN KEY (submission) REFERENCES submission(id)
-              );
-            ",
-            )
-            .await
-            .unwrap();
+    /// Deletes a problem with its revisions, rubric and extensions, or only
+    /// marks it deleted when submissions refer to it. Returns whether there
+    /// was a problem to delete.
+    pub async fn delete_problem(&self, id: i64) -> Result<bool, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::remove_problem(&db, id).await;
+        Self::finish_transaction(&mut db, result).await
     }
 
-    pub async fn create_problem(
-        &self,
-        problem: CreateProblem,
-    ) -> Result<i64, Box<dyn std::error::Error>> {
-        let db = &self.conn;
-        db.execute(
-                "INSERT INTO problem (name, desc) VALUES (?, ?)",
-                [problem.name.as_str(), problem.desc.as_str()],
-            )
+    async fn remove_problem(db: &Connection, id: i64) -> Result<bool, SubmError> {
+        let mut rows = db
+            .query("SELECT COUNT(*) FROM submission WHERE problem = ?", [id])
             .await?;
+        let submissions: i64 = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => 0,
+        };
+        drop(rows);
 
-        let id = db.last_insert_rowid();
-        Ok(id)
+        let changed = if submissions > 0 {
+            db.execute(
+                    "UPDATE problem SET deleted = 1, updated_at = ? WHERE id = ? AND deleted = 0",
+                    [Utc::now().timestamp(), id],
+                )
+                .await?
+        } else {
+            let deleted = db
+                .execute("DELETE FROM problem WHERE id = ? AND deleted = 0", [id])
+                .await?;
+            // The next problem gets the same ID and mustn't inherit these
+            if deleted > 0 {
+                for table in ["extension", "rubric_criterion", "problem_revision"] {
+                    db.execute(&format!("DELETE FROM {} WHERE problem = ?", table), [id])
+                        .await?;
+                }
+            }
+            deleted
+        };
+        Ok(changed > 0)
     }
 
-    pub async fn get_problems(&self) -> Result<Vec<Problem>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
+    /// Lists the problems matching `query` in every course visible to `member`
+    pub async fn get_problems(
+        &self,
+        member: Option<i64>,
+        query: &ProblemQuery,
+    ) -> Result<Page<Problem>, SubmError> {
+        let db = self.conn()?;
+        let course = query.course;
+        let (since, until) = query.range().bounds();
+        let cursor = query.cursor;
         let mut rows = db
-            .query("SELECT id, name, desc FROM problem ORDER BY id DESC", ())
+            .query(
+                &format!(
+                    "SELECT {} FROM problem p
+                     LEFT JOIN course_member m ON m.course = p.course AND m.user = ?
+                     WHERE p.deleted = 0
+                     AND (? IS NULL OR p.course = ?)
+                     AND (? IS NULL OR m.user IS NOT NULL)
+                     AND (? IS NULL OR p.created_at >= ?)
+                     AND (? IS NULL OR p.created_at < ?)
+                     AND (? IS NULL OR p.id {} ?)
+                     ORDER BY p.id {} {}",
+                    PROBLEM_COLUMNS,
+                    query.sort.after(),
+                    query.sort.sql(),
+                    limit_clause(query.limit)
+                ),
+                [member, course, course, member, since, since, until, until, cursor, cursor],
+            )
             .await?;
 
-
         let mut problems = Vec::new();
         while let Some(row) = rows.next().await? {
-            problems.push(Problem {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                desc: row.get(2)?,
-            });
+            problems.push(Self::problem_from_row(&row)?);
         }
-        Ok(problems)
+        let mut page = Page::from_rows(problems, query.limit, |problem| problem.id);
+        Self::load_rubrics(&db, &mut page.items).await?;
+        Ok(page)
     }
 
     pub async fn get_problem_by_id(
         &self,
         id: i64,
-    ) -> Result<Option<Problem>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
+        member: Option<i64>,
+    ) -> Result<Option<Problem>, SubmError> {
+        let db = self.conn()?;
         let mut rows = db
-            .query("SELECT id, name, desc FROM problem WHERE id = ?", [id])
+            .query(
+                &format!(
+                    "SELECT {} FROM problem p
+                     LEFT JOIN course_member m ON m.course = p.course AND m.user = ?
+                     WHERE p.id = ? AND (? IS NULL OR m.user IS NOT NULL)",
+                    PROBLEM_COLUMNS
+                ),
+                (member, id, member),
+            )
             .await?;
 
-        if let Some(row) = rows.next().await? {
-            Ok(Some(Problem {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                desc: row.get(2)?,
-            }))
-        } else {
-            Ok(None)
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let mut problems = [Self::problem_from_row(&row)?];
+        Self::load_rubrics(&db, &mut problems).await?;
+        let [problem] = problems;
+        Ok(Some(problem))
+    }
+
+    // Fills in the rubrics of `problems` with one query
+    async fn load_rubrics(db: &Connection, problems: &mut [Problem]) -> Result<(), SubmError> {
+        if problems.is_empty() {
+            return Ok(());
+        }
+
+        let mut rows = db
+            .query(
+                &format!(
+                    "SELECT problem, id, name, points FROM rubric_criterion
+                     WHERE problem IN ({}) ORDER BY problem, position",
+                    id_list(problems.iter().map(|problem| problem.id))
+                ),
+                (),
+            )
+            .await?;
+
+        let mut rubrics: HashMap<i64, Vec<RubricCriterion>> = HashMap::new();
+        while let Some(row) = rows.next().await? {
+            rubrics.entry(row.get(0)?).or_default().push(RubricCriterion {
+                id: row.get(1)?,
+                name: row.get(2)?,
+                points: row.get(3)?,
+            });
+        }
+        for problem in problems {
+            problem.rubric = rubrics.remove(&problem.id).unwrap_or_default();
         }
+        Ok(())
     }
 
-    pub async fn store_file(
-        &self,
-        filename: &str,
-        content: &[u8],
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        // Calculate SHA256 hash
-        let mut hasher = Sha256::new();
-        hasher.update(content);
-        let hash = format!("{:x}", hasher.finalize());
+    fn problem_from_row(row: &turso::Row) -> Result<Problem, SubmError> {
+        let allowed_extensions: Option<String> = row.get(7)?;
+        let deleted: i64 = row.get(8)?;
+        Ok(Problem {
+            id: row.get(0)?,
+            course: row.get(1)?,
+            name: row.get(2)?,
+            desc: row.get(3)?,
+            limits: ProblemLimits {
+                max_files: row.get(4)?,
+                max_file_size: row.get(5)?,
+                max_total_size: row.get(6)?,
+                allowed_extensions: allowed_extensions
+                    .map(|exts| exts.split(',').map(str::to_string).collect()),
+            },
+            schedule: ProblemSchedule {
+                opens_at: from_timestamp(row.get(10)?),
+                soft_deadline: from_timestamp(row.get(11)?),
+                hard_deadline: from_timestamp(row.get(12)?),
+            },
+            deleted: deleted != 0,
+            revision: row.get(9)?,
+            max_score: row.get(13)?,
+            // Filled in by `load_rubrics`
+            rubric: Vec::new(),
+            created_at: from_timestamp(row.get(14)?),
+            updated_at: from_timestamp(row.get(15)?),
+        })
+    }
 
-        // Store file with hash as filename
-        let file_path = format!("{}/{}", self.uploads_dir, hash);
-        let mut file = fs::File::create(&file_path)?;
-        file.write_all(content)?;
+    /// Where a blob sits on the local filesystem, if the store keeps it there
+    pub fn local_blob_path(&self, hash: &str) -> Option<PathBuf> {
+        self.store.local_path(hash)
+    }
 
-        let db = &self.conn;
+    /// Starts writing an upload to the staging area, where it stays until a
+    /// submission referencing it commits
+    pub async fn blob_writer(&self) -> std::io::Result<BlobWriter> {
+        let staging = Path::new(&self.uploads_dir).join(STAGING_DIR);
+        let file = tokio::task::spawn_blocking(move || NamedTempFile::new_in(staging))
+            .await
+            .map_err(std::io::Error::other)??;
+        let writer = tokio::fs::File::from_std(file.as_file().try_clone()?);
+        Ok(BlobWriter {
+            file,
+            writer,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
 
-        // Insert file debug into database (check if exists first)
-        let mut existing_rows = db
-            .query("SELECT id FROM file WHERE hash = ?", [hash.as_str()])
-            .await?;
+    /// Stages content that is already in memory
+    pub async fn stage_blob(&self, content: &[u8]) -> std::io::Result<StagedBlob> {
+        let mut writer = self.blob_writer().await?;
+        writer.write(content).await?;
+        writer.finish().await
+    }
 
-        if existing_rows.next().await?.is_none() {
-            db.execute(
-                    "INSERT INTO file (name, hash) VALUES (?, ?)",
-                    (filename, hash.as_str()),
-                )
+    /// Moves a staged blob into the store
+    async fn publish_blob(&self, blob: StagedBlob) -> Result<(), SubmError> {
+        self.store.put(&blob.hash, blob.file).await?;
+        Ok(())
+    }
+
+    async fn file_id(db: &Connection, hash: &str, now: i64) -> Result<i64, SubmError> {
+        {
+            let mut existing_rows = db
+                .query("SELECT id FROM file WHERE hash = ?", [hash])
                 .await?;
+            if let Some(row) = existing_rows.next().await? {
+                return Ok(row.get(0)?);
+            }
         }
 
-        Ok(hash)
+        db.execute(
+                "INSERT INTO file (hash, created_at, updated_at) VALUES (?, ?, ?)",
+                (hash, now, now),
+            )
+            .await?;
+        Ok(db.last_insert_rowid())
     }
 
+    /// Stores a submission with all its staged files, or nothing at all if any
+    /// step fails. Each file comes with the relative path it was uploaded under.
     pub async fn create_submission_with_files(
         &self,
         problem_id: i64,
+        author: i64,
+        submission: CreateSubmission,
+        staged: Vec<(String, StagedBlob)>,
+    ) -> Result<i64, SubmError> {
+        let staged = staged
+            .into_iter()
+            .map(|(path, blob)| Ok((paths::sanitize_path(&path)?, blob)))
+            .collect::<Result<Vec<_>, SubmError>>()?;
+        paths::check_paths(staged.iter().map(|(path, _)| path.as_str()))?;
+
+        // Storage is dealt with before the transaction starts, so it never
+        // holds the database up; until the rows are committed, the grace
+        // period keeps garbage collection away from the blobs. Blobs of a
+        // submission that fails stay for garbage collection too: another one
+        // with the same content may be about to reference them.
+        let mut files = Vec::new();
+        for (path, blob) in staged {
+            let hash = blob.hash.clone();
+            self.publish_blob(blob).await?;
+            files.push((path, hash));
+        }
+
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::insert_submission(&db, problem_id, author, submission, &files).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn insert_submission(
+        db: &Connection,
+        problem_id: i64,
+        author: i64,
         submission: CreateSubmission,
-        files: Vec<(String, Vec<u8>)>,
-    ) -> Result<i64, Box<dyn std::error::Error>> {
-        let db = &self.conn;
-        // Create submission
+        files: &[(String, String)],
+    ) -> Result<i64, SubmError> {
+        // Create submission against the problem's current revision
+        let mut rows = db
+            .query("SELECT revision FROM problem WHERE id = ?", [problem_id])
+            .await?;
+        let revision: Option<i64> = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => return Err(SubmError::NotFound("Problem")),
+        };
+        drop(rows);
+
+        let now = Utc::now().timestamp();
         db.execute(
-                "INSERT INTO submission (comment, problem) VALUES (?, ?)",
-                [submission.comment.as_str(), problem_id.to_string().as_str()],
+                "INSERT INTO submission (comment, problem, author, revision, late, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                (submission.comment.as_str(), problem_id, author, revision, submission.late, now, now),
             )
             .await?;
 
         let submission_id = db.last_insert_rowid();
 
-        // Store files and create attachments
-        for (filename, content) in files {
-            let hash = self.store_file(&filename, &content).await?;
+        // Identical content shares one blob, but each attachment keeps the
+        // path it came with
+        for (path, hash) in files {
+            let file_id = Self::file_id(db, hash, now).await?;
 
-            // Get file ID
-            let mut file_rows = db
-                .query("SELECT id FROM file WHERE hash = ?", [hash.as_str()])
+            db.execute(
+                    "INSERT INTO attachment (submission, file, name, path, created_at, updated_at)
+                     VALUES (?, ?, ?, ?, ?, ?)",
+                    (submission_id, file_id, paths::file_name(path), path.as_str(), now, now),
+                )
                 .await?;
-            let file_row = file_rows.next().await?.ok_or("File not found")?;
-            let file_id: i64 = file_row.get(0)?;
+        }
+
+        Ok(submission_id)
+    }
+
+    /// Finds blobs no `file` row references and `file` rows no submission
+    /// attaches, and removes them unless `dry_run` is set. Anything newer than
+    /// `grace` is left alone, since a submission being stored right now may be
+    /// about to reference it.
+    pub async fn collect_garbage(&self, dry_run: bool, grace: Duration) -> Result<GcReport, SubmError> {
+        let mut db = self.conn()?;
+        let cutoff = SystemTime::now() - grace;
+        let cutoff_secs = DateTime::<Utc>::from(cutoff).timestamp();
+        let mut report = GcReport {
+            orphan_files: Self::unattached_files(&db, cutoff_secs, None).await?,
+            ..GcReport::default()
+        };
+        if !dry_run && !report.orphan_files.is_empty() {
+            db.begin().await?;
+            let result = Self::remove_files(&db, cutoff_secs, &report.orphan_files).await;
+            report.orphan_files = Self::finish_transaction(&mut db, result).await?;
+        }
+
+        let mut referenced = HashSet::new();
+        {
+            let mut rows = db.query("SELECT id, hash FROM file", ()).await?;
+            while let Some(row) = rows.next().await? {
+                let id: i64 = row.get(0)?;
+                let hash: String = row.get(1)?;
+                // On a dry run the orphaned rows are still there. A real run
+                // removed them, so whatever is left keeps its blob.
+                if !(dry_run && report.orphan_files.contains(&id)) {
+                    referenced.insert(hash);
+                }
+            }
+        }
+
+        for staged in fs::read_dir(Path::new(&self.uploads_dir).join(STAGING_DIR))? {
+            let staged = staged?;
+            let meta = staged.metadata()?;
+            if meta.is_file() && meta.modified()? < cutoff {
+                report.stale_staged += 1;
+                report.reclaimable_bytes += meta.len();
+                if !dry_run {
+                    fs::remove_file(staged.path())?;
+                }
+            }
+        }
+
+        for blob in self.store.list().await? {
+            if referenced.contains(&blob.hash) || blob.modified >= cutoff {
+                continue;
+            }
+            // A submission reusing the blob refreshed its time when it put
+            // it into the store, but may have done that after the listing.
+            // It's referenced for good once that submission commits.
+            if !dry_run && Self::blob_referenced(&db, &blob.hash).await? {
+                continue;
+            }
+            report.reclaimable_bytes += blob.size;
+            if !dry_run {
+                self.store.delete(&blob.hash).await?;
+            }
+            report.orphan_blobs.push(blob.hash);
+        }
+        report.orphan_blobs.sort();
+
+        if !dry_run {
+            info!(
+                "Garbage collection removed {} blobs, {} file rows and {} staged uploads, {} bytes",
+                report.orphan_blobs.len(),
+                report.orphan_files.len(),
+                report.stale_staged,
+                report.reclaimable_bytes
+            );
+        }
+        Ok(report)
+    }
+
+    async fn blob_referenced(db: &Connection, hash: &str) -> Result<bool, SubmError> {
+        let mut rows = db.query("SELECT 1 FROM file WHERE hash = ?", [hash]).await?;
+        Ok(rows.next().await?.is_some())
+    }
+
+    // IDs of `file` rows created before `cutoff` that no attachment points
+    // at, only among `ids` if given. Rows from before timestamps were
+    // recorded count as old.
+    async fn unattached_files(
+        db: &Connection,
+        cutoff: i64,
+        ids: Option<&[i64]>,
+    ) -> Result<Vec<i64>, SubmError> {
+        let among = match ids {
+            Some(ids) => format!("AND f.id IN ({})", id_list(ids.iter().copied())),
+            None => String::new(),
+        };
+        let mut rows = db
+            .query(
+                &format!(
+                    "SELECT f.id FROM file f
+                     LEFT JOIN attachment a ON a.file = f.id
+                     WHERE a.file IS NULL AND COALESCE(f.created_at, 0) < ? {}
+                     ORDER BY f.id",
+                    among
+                ),
+                [cutoff],
+            )
+            .await?;
+
+        let mut files = Vec::new();
+        while let Some(row) = rows.next().await? {
+            files.push(row.get(0)?);
+        }
+        Ok(files)
+    }
 
-            // Create attachment
+    // Deletes the `file` rows among `ids` that are still unattached, in case
+    // a new submission picked one up meanwhile, and returns those deleted
+    async fn remove_files(db: &Connection, cutoff: i64, ids: &[i64]) -> Result<Vec<i64>, SubmError> {
+        let unattached = Self::unattached_files(db, cutoff, Some(ids)).await?;
+        if !unattached.is_empty() {
             db.execute(
-                    "INSERT INTO attachment (submission, file) VALUES (?, ?)",
-                    [
-                        submission_id.to_string().as_str(),
-                        file_id.to_string().as_str(),
-                    ],
+                    &format!(
+                        "DELETE FROM file WHERE id IN ({})",
+                        id_list(unattached.iter().copied())
+                    ),
+                    (),
                 )
                 .await?;
         }
+        Ok(unattached)
+    }
+
+    /// Re-hashes every blob in the store and cross-checks it against the
+    /// `file` and `attachment` tables, changing nothing
+    pub async fn fsck(&self) -> Result<FsckReport, SubmError> {
+        let db = self.conn()?;
+        let mut report = FsckReport::default();
+
+        for blob in self.store.list().await? {
+            // Something else may have removed it since it was listed
+            let Some((_, reader)) = self.store.get(&blob.hash).await? else {
+                continue;
+            };
+            report.checked += 1;
+            if hash_blob(reader).await? != blob.hash {
+                report.corrupt.push(blob.hash);
+            }
+        }
+        report.corrupt.sort();
+
+        {
+            let mut rows = db
+                .query("SELECT DISTINCT hash FROM file WHERE hash IS NOT NULL ORDER BY hash", ())
+                .await?;
+            while let Some(row) = rows.next().await? {
+                let hash: String = row.get(0)?;
+                if !self.store.exists(&hash).await? {
+                    report.missing.push(hash);
+                }
+            }
+        }
 
-        Ok(submission_id)
+        report.unattached_files = Self::unattached_files(&db, i64::MAX, None).await?;
+
+        let mut rows = db
+            .query(
+                "SELECT a.submission, a.file FROM attachment a
+                 LEFT JOIN submission s ON s.id = a.submission
+                 LEFT JOIN file f ON f.id = a.file
+                 WHERE s.id IS NULL OR f.id IS NULL
+                 ORDER BY a.rowid",
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            report.dangling_attachments.push(DanglingAttachment {
+                submission: row.get(0)?,
+                file: row.get(1)?,
+            });
+        }
+
+        Ok(report)
+    }
+
+    /// Streams a blob out of the store along with its size. With blob
+    /// verification on, it is checked against its hash on the way: the last
+    /// chunk is held back until the whole blob has been hashed, and a
+    /// mismatch ends the stream with an error instead, so a client never
+    /// receives a corrupt file in full.
+    pub async fn blob_stream(
+        &self,
+        hash: &str,
+    ) -> Result<(u64, impl Stream<Item = std::io::Result<Bytes>> + use<>), SubmError> {
+        // The file is known, so a missing blob means the store is damaged
+        let (size, reader) = self.store.get(hash).await?.ok_or_else(|| missing_blob(hash))?;
+        Ok((size, read_blob(reader, self.verify_blobs.then(|| hash.to_string()))))
+    }
+
+    /// Streams `length` bytes of a blob from `start` on. Only whole blobs can
+    /// be checked against their hash, so this part isn't.
+    pub async fn blob_range_stream(
+        &self,
+        hash: &str,
+        start: u64,
+        length: u64,
+    ) -> Result<impl Stream<Item = std::io::Result<Bytes>> + use<>, SubmError> {
+        let reader = self
+            .store
+            .get_range(hash, start, length)
+            .await?
+            .ok_or_else(|| missing_blob(hash))?;
+        Ok(read_blob(reader, None))
+    }
+
+    pub async fn blob_size(&self, hash: &str) -> Result<u64, SubmError> {
+        self.store.size(hash).await?.ok_or_else(|| missing_blob(hash))
     }
 
-    pub async fn get_submissions(&self) -> Result<Vec<Submission>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
+    /// Lists the submissions matching `query`, only those of `author` if given
+    pub async fn get_submissions(
+        &self,
+        author: Option<i64>,
+        query: &SubmissionQuery,
+    ) -> Result<Page<Submission>, SubmError> {
+        let db = self.conn()?;
+        let (course, problem, student) = (query.course, query.problem, query.student);
+        let (since, until) = query.range().bounds();
+        let cursor = query.cursor;
+        // The state is one of a fixed set of names, safe to put in the query
+        let status = query
+            .status
+            .map_or(String::new(), |status| format!("AND s.state = '{}'", status.as_str()));
         let mut rows = db.query(
-                "SELECT id, comment, problem FROM submission ORDER BY id DESC",
-                (),
+                &format!(
+                    "SELECT {} FROM submission s JOIN problem p ON p.id = s.problem
+                     WHERE (? IS NULL OR s.author = ?)
+                     AND (? IS NULL OR p.course = ?)
+                     AND (? IS NULL OR s.problem = ?)
+                     AND (? IS NULL OR s.author = ?)
+                     AND (? IS NULL OR s.created_at >= ?)
+                     AND (? IS NULL OR s.created_at < ?)
+                     AND (? IS NULL OR s.id {} ?)
+                     {}
+                     ORDER BY s.id {} {}",
+                    SUBMISSION_COLUMNS,
+                    query.sort.after(),
+                    status,
+                    query.sort.sql(),
+                    limit_clause(query.limit)
+                ),
+                [
+                    author, author, course, course, problem, problem, student, student, since,
+                    since, until, until, cursor, cursor,
+                ],
             )
             .await?;
 
         let mut submissions = Vec::new();
         while let Some(row) = rows.next().await? {
-            let submission_id: i64 = row.get(0)?;
-            let comment: String = row.get(1)?;
-            let problem: i64 = row.get(2)?;
-
-            // Get files for this submission
-            let files = self.get_submission_files(submission_id).await?;
-
-            // Get feedback for this submission
-            let feedbacks = self.get_submission_feedbacks(submission_id).await?;
-            let accepted = feedbacks.first().map_or(false, |f| f.grade == 1);
-
-            submissions.push(Submission {
-                id: submission_id,
-                comment,
-                problem,
-                files,
-                status: SubmissionStatus {
-                    accepted,
-                    feedbacks,
-                },
-            });
+            submissions.push(Self::submission_from_row(&row)?);
         }
-        Ok(submissions)
+        let mut page = Page::from_rows(submissions, query.limit, |submission| submission.id);
+        Self::load_submission_details(&db, &mut page.items).await?;
+        Ok(page)
     }
 
+    /// Looks up a submission, treating other authors' work as missing if `author` is given
     pub async fn get_submission_by_id(
         &self,
         id: i64,
-    ) -> Result<Option<Submission>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
+        author: Option<i64>,
+    ) -> Result<Option<Submission>, SubmError> {
+        let db = self.conn()?;
         let mut rows = db.query(
-                "SELECT id, comment, problem FROM submission WHERE id = ?",
-                [id],
+                &format!(
+                    "SELECT {} FROM submission s JOIN problem p ON p.id = s.problem
+                     WHERE s.id = ? AND (? IS NULL OR s.author = ?)",
+                    SUBMISSION_COLUMNS
+                ),
+                (id, author, author),
             )
             .await?;
 
-        if let Some(row) = rows.next().await? {
-            let submission_id: i64 = row.get(0)?;
-            let comment: String = row.get(1)?;
-            let problem: i64 = row.get(2)?;
-
-            // Get files for this submission
-            let files = self.get_submission_files(submission_id).await?;
-
-            // Get feedback for this submission
-            let feedbacks = self.get_submission_feedbacks(submission_id).await?;
-            let accepted = feedbacks.last().map_or(false, |f| f.grade == 1);
-
-            Ok(Some(Submission {
-                id: submission_id,
-                comment,
-                problem,
-                files,
-                status: SubmissionStatus {
-                    accepted,
-                    feedbacks,
-                },
-            }))
-        } else {
-            Ok(None)
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let mut submissions = [Self::submission_from_row(&row)?];
+        Self::load_submission_details(&db, &mut submissions).await?;
+        let [submission] = submissions;
+        Ok(Some(submission))
+    }
+
+    // Files and feedback are left empty for `load_submission_details`
+    fn submission_from_row(row: &turso::Row) -> Result<Submission, SubmError> {
+        let late: i64 = row.get(5)?;
+        let max_score: i64 = row.get(6)?;
+        let state: String = row.get(7)?;
+        Ok(Submission {
+            id: row.get(0)?,
+            comment: row.get(1)?,
+            problem: row.get(2)?,
+            author: row.get(3)?,
+            revision: row.get(4)?,
+            late: late != 0,
+            files: Vec::new(),
+            status: SubmissionStatus::new(parse_state(&state)?, Vec::new(), max_score),
+            created_at: from_timestamp(row.get(8)?),
+            updated_at: from_timestamp(row.get(9)?),
+        })
+    }
+
+    // Fills in the files and feedback of `submissions` with a fixed number of
+    // queries however many there are
+    async fn load_submission_details(
+        db: &Connection,
+        submissions: &mut [Submission],
+    ) -> Result<(), SubmError> {
+        if submissions.is_empty() {
+            return Ok(());
+        }
+        let ids = id_list(submissions.iter().map(|submission| submission.id));
+
+        let mut files: HashMap<i64, Vec<FileInfo>> = HashMap::new();
+        let mut rows = db
+            .query(
+                &format!(
+                    "SELECT a.submission, f.id, a.name, a.path, f.hash, f.created_at, f.updated_at
+                     FROM file f JOIN attachment a ON f.id = a.file
+                     WHERE a.submission IN ({})
+                     ORDER BY a.rowid",
+                    ids
+                ),
+                (),
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            files.entry(row.get(0)?).or_default().push(FileInfo {
+                id: row.get(1)?,
+                name: row.get(2)?,
+                path: row.get(3)?,
+                hash: row.get(4)?,
+                created_at: from_timestamp(row.get(5)?),
+                updated_at: from_timestamp(row.get(6)?),
+            });
+        }
+
+        let mut rows = db
+            .query(
+                &format!(
+                    "SELECT {} FROM feedback f LEFT JOIN user u ON u.id = f.author
+                     WHERE f.submission IN ({}) AND f.retracted_at IS NULL
+                     ORDER BY f.id DESC",
+                    FEEDBACK_COLUMNS, ids
+                ),
+                (),
+            )
+            .await?;
+        let mut all_feedbacks = Vec::new();
+        while let Some(row) = rows.next().await? {
+            all_feedbacks.push(Self::feedback_from_row(&row)?);
+        }
+        Self::load_feedback_points(db, &mut all_feedbacks).await?;
+
+        let mut feedbacks: HashMap<i64, Vec<Feedback>> = HashMap::new();
+        for feedback in all_feedbacks {
+            feedbacks.entry(feedback.submission).or_default().push(feedback);
+        }
+
+        for submission in submissions {
+            submission.files = files.remove(&submission.id).unwrap_or_default();
+            let (state, max_score) = (submission.status.state, submission.status.max_score);
+            let feedbacks = feedbacks.remove(&submission.id).unwrap_or_default();
+            submission.status = SubmissionStatus::new(state, feedbacks, max_score);
         }
+        Ok(())
     }
 
-    async fn get_submission_files(
+    /// Looks up a feedback that hasn't been retracted, treating feedback on
+    /// other authors' submissions as missing if `author` is given
+    pub async fn get_feedback_by_id(
         &self,
-        submission_id: i64,
-    ) -> Result<Vec<FileInfo>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
-        let mut rows = db.query(
-            "SELECT f.id, f.name, f.hash FROM file f JOIN attachment a ON f.id = a.file WHERE a.submission = ?",
-            [submission_id.to_string().as_str()]
-        ).await?;
+        id: i64,
+        author: Option<i64>,
+    ) -> Result<Option<Feedback>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                &format!(
+                    "SELECT {} FROM feedback f LEFT JOIN user u ON u.id = f.author
+                     JOIN submission s ON s.id = f.submission
+                     WHERE f.id = ? AND f.retracted_at IS NULL
+                     AND (? IS NULL OR s.author = ?)",
+                    FEEDBACK_COLUMNS
+                ),
+                (id, author, author),
+            )
+            .await?;
 
-        let mut files = Vec::new();
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let mut feedbacks = [Self::feedback_from_row(&row)?];
+        Self::load_feedback_points(&db, &mut feedbacks).await?;
+        let [feedback] = feedbacks;
+        Ok(Some(feedback))
+    }
+
+    // Points are left empty for `load_feedback_points`
+    fn feedback_from_row(row: &turso::Row) -> Result<Feedback, SubmError> {
+        let state: String = row.get(4)?;
+        Ok(Feedback {
+            id: row.get(0)?,
+            submission: row.get(1)?,
+            grade: row.get(2)?,
+            score: row.get(3)?,
+            points: Vec::new(),
+            state: parse_state(&state)?,
+            message: row.get(5)?,
+            author: row.get(6)?,
+            author_name: row.get(7)?,
+            created_at: from_timestamp(row.get(8)?),
+            updated_at: from_timestamp(row.get(9)?),
+        })
+    }
+
+    /// Earlier messages of a feedback, oldest first
+    pub async fn get_feedback_edits(&self, feedback_id: i64) -> Result<Vec<FeedbackEdit>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT message, replaced_at FROM feedback_edit WHERE feedback = ? ORDER BY id",
+                [feedback_id],
+            )
+            .await?;
+
+        let mut edits = Vec::new();
         while let Some(row) = rows.next().await? {
-            files.push(FileInfo {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                hash: row.get(2)?,
+            let replaced_at: i64 = row.get(1)?;
+            edits.push(FeedbackEdit {
+                message: row.get(0)?,
+                replaced_at: from_timestamp(Some(replaced_at)).ok_or_else(|| {
+                    SubmError::Internal(format!("Bad feedback edit date {}", replaced_at))
+                })?,
             });
         }
-        Ok(files)
+        Ok(edits)
     }
 
-    async fn get_submission_feedbacks(
-        &self,
-        submission_id: i64,
-    ) -> Result<Vec<Feedback>, Box<dyn std::error::Error>> {
-        let db = &self.conn;
+    /// Replaces the message of a feedback, keeping the old one in its history.
+    /// Returns false if there's no such feedback or it has been retracted.
+    pub async fn edit_feedback(&self, id: i64, edit: EditFeedback) -> Result<bool, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::apply_feedback_edit(&db, id, edit).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn apply_feedback_edit(db: &Connection, id: i64, edit: EditFeedback) -> Result<bool, SubmError> {
+        let now = Utc::now().timestamp();
+        let saved = db
+            .execute(
+                "INSERT INTO feedback_edit (feedback, message, replaced_at, created_at, updated_at)
+                 SELECT id, message, ?, ?, ? FROM feedback WHERE id = ? AND retracted_at IS NULL",
+                [now, now, now, id],
+            )
+            .await?;
+        if saved == 0 {
+            return Ok(false);
+        }
+
+        db.execute(
+                "UPDATE feedback SET message = ?, updated_at = ? WHERE id = ?",
+                (edit.message.as_deref().unwrap_or(""), now, id),
+            )
+            .await?;
+        Ok(true)
+    }
+
+    /// Withdraws a feedback, putting its submission back in the state the
+    /// feedback before it gave, or back to pending if there was none. Returns
+    /// false if there's nothing to retract.
+    pub async fn retract_feedback(&self, id: i64) -> Result<bool, SubmError> {
+        let mut db = self.conn()?;
+        db.begin().await?;
+        let result = Self::apply_feedback_retraction(&db, id).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    async fn apply_feedback_retraction(db: &Connection, id: i64) -> Result<bool, SubmError> {
+        let now = Utc::now().timestamp();
+        let retracted = db
+            .execute(
+                "UPDATE feedback SET retracted_at = ? WHERE id = ? AND retracted_at IS NULL",
+                [now, id],
+            )
+            .await?;
+        if retracted == 0 {
+            return Ok(false);
+        }
+
+        let mut rows = db
+            .query("SELECT submission FROM feedback WHERE id = ?", [id])
+            .await?;
+        let submission: i64 = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => return Err(SubmError::NotFound("Feedback")),
+        };
+        drop(rows);
+
+        // The submission falls back to the verdict of the feedback still
+        // standing, or to pending if none is
+        let mut rows = db
+            .query(
+                "SELECT state FROM feedback
+                 WHERE submission = ? AND retracted_at IS NULL
+                 ORDER BY id DESC LIMIT 1",
+                [submission],
+            )
+            .await?;
+        let state: String = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => SubmissionState::Pending.as_str().to_string(),
+        };
+        drop(rows);
+
+        db.execute(
+                "UPDATE submission SET updated_at = ?, state = ? WHERE id = ?",
+                (now, state, submission),
+            )
+            .await?;
+        Ok(true)
+    }
+
+    // Fills in the rubric points of `feedbacks` with one query
+    async fn load_feedback_points(
+        db: &Connection,
+        feedbacks: &mut [Feedback],
+    ) -> Result<(), SubmError> {
+        if feedbacks.is_empty() {
+            return Ok(());
+        }
+
         let mut rows = db
             .query(
-                "SELECT id, grade, message FROM feedback WHERE submission = ? ORDER BY id DESC",
-                [submission_id.to_string().as_str()],
+                &format!(
+                    "SELECT fp.feedback, fp.criterion, fp.points FROM feedback_points fp
+                     JOIN rubric_criterion c ON c.id = fp.criterion
+                     WHERE fp.feedback IN ({}) ORDER BY c.position",
+                    id_list(feedbacks.iter().map(|feedback| feedback.id))
+                ),
+                (),
             )
             .await?;
 
-        let mut feedbacks = Vec::new();
+        let mut points: HashMap<i64, Vec<CriterionScore>> = HashMap::new();
         while let Some(row) = rows.next().await? {
-            feedbacks.push(Feedback {
-                id: row.get(0)?,
-                grade: row.get(1)?,
-                message: row.get(2)?,
+            points.entry(row.get(0)?).or_default().push(CriterionScore {
+                criterion: row.get(1)?,
+                points: row.get(2)?,
             });
         }
-        Ok(feedbacks)
+        for feedback in feedbacks {
+            feedback.points = points.remove(&feedback.id).unwrap_or_default();
+        }
+        Ok(())
     }
 
     pub async fn create_feedback(
         &self,
         submission_id: i64,
+        author: i64,
         feedback: CreateFeedback,
-    ) -> Result<i64, Box<dyn std::error::Error>> {
-        let db = &self.conn;
-        // Validate grade is 0 or 1
-        if feedback.grade != 0 && feedback.grade != 1 {
-            return Err("Grade must be either 0 or 1".into());
-        }
+    ) -> Result<i64, SubmError> {
+        let mut db = self.conn()?;
+        let (problem_id, max_score): (i64, i64) = {
+            let mut rows = db
+                .query(
+                    "SELECT p.id, p.max_score FROM submission s
+                     JOIN problem p ON p.id = s.problem WHERE s.id = ?",
+                    [submission_id],
+                )
+                .await?;
+            match rows.next().await? {
+                Some(row) => (row.get(0)?, row.get(1)?),
+                None => return Err(SubmError::NotFound("Submission")),
+            }
+        };
+        let rubric = Self::get_rubric(&db, problem_id).await?;
+        let verdict = grading::score_feedback(&feedback, max_score, &rubric)?;
 
         // Always insert new feedback (multiple feedbacks allowed)
+        db.begin().await?;
+        let result = Self::insert_feedback(&db, submission_id, author, &verdict, &feedback).await;
+        Self::finish_transaction(&mut db, result).await
+    }
+
+    // Records the feedback and moves the submission to the state it gives
+    async fn insert_feedback(
+        db: &Connection,
+        submission_id: i64,
+        author: i64,
+        verdict: &Verdict,
+        feedback: &CreateFeedback,
+    ) -> Result<i64, SubmError> {
+        let mut rows = db
+            .query("SELECT state FROM submission WHERE id = ?", [submission_id])
+            .await?;
+        let current: String = match rows.next().await? {
+            Some(row) => row.get(0)?,
+            None => return Err(SubmError::NotFound("Submission")),
+        };
+        let current = parse_state(&current)?;
+        if !current.can_become(verdict.state) {
+            return Err(SubmError::Conflict(format!(
+                "Submission can't go from {} to {}",
+                current.as_str(),
+                verdict.state.as_str()
+            )));
+        }
+
+        let now = Utc::now().timestamp();
         db.execute(
-                "INSERT INTO feedback (submission, grade, message) VALUES (?, ?, ?)",
-                [
-                    submission_id.to_string().as_str(),
-                    feedback.grade.to_string().as_str(),
+                "INSERT INTO feedback (submission, grade, score, state, message, author, created_at, updated_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                (
+                    submission_id,
+                    verdict.grade,
+                    verdict.score,
+                    verdict.state.as_str(),
                     feedback.message.as_deref().unwrap_or(""),
-                ],
+                    author,
+                    now,
+                    now,
+                ),
             )
             .await?;
-
         let feedback_id = db.last_insert_rowid();
+
+        db.execute(
+                "UPDATE submission SET state = ?, updated_at = ? WHERE id = ?",
+                (verdict.state.as_str(), now, submission_id),
+            )
+            .await?;
+        for criterion in &feedback.points {
+            db.execute(
+                    "INSERT INTO feedback_points (feedback, criterion, points, created_at, updated_at)
+                     VALUES (?, ?, ?, ?, ?)",
+                    [feedback_id, criterion.criterion, criterion.points, now, now],
+                )
+                .await?;
+        }
         Ok(feedback_id)
     }
 
-    pub async fn get_file_content(
+    /// Name the blob was uploaded under, if it is attached to a submission
+    /// visible under the `author` filter. Identical content can be attached
+    /// under several names, so `submission` picks whose name to use; without
+    /// it the earliest attachment's is.
+    pub async fn get_file_name(
         &self,
         hash: &str,
-    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let file_path = format!("{}/{}", self.uploads_dir, hash);
-        let content = fs::read(&file_path)?;
-        Ok(content)
+        author: Option<i64>,
+        submission: Option<i64>,
+    ) -> Result<Option<String>, SubmError> {
+        let db = self.conn()?;
+        let mut rows = db
+            .query(
+                "SELECT a.name FROM file f
+                 JOIN attachment a ON f.id = a.file
+                 JOIN submission s ON s.id = a.submission
+                 WHERE f.hash = ? AND (? IS NULL OR s.author = ?) AND (? IS NULL OR s.id = ?)
+                 ORDER BY a.rowid
+                 LIMIT 1",
+                (hash, author, author, submission, submission),
+            )
+            .await?;
+        match rows.next().await? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
     }
 }
@@ -1,12 +1,114 @@
-use actix_web::{ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
 use derive_more::Display;
 
+use log::error;
+
 #[derive(Display, Debug)]
 pub enum SubmError {
-    FileNotFound,
-    GenericError,
+    /// The named thing doesn't exist, or isn't visible to the caller
+    #[display("{_0} not found")]
+    NotFound(&'static str),
+    /// The request can't be parsed at all
+    #[display("{_0}")]
+    BadRequest(String),
+    /// The request is well-formed but its content is unacceptable
+    #[display("{_0}")]
+    Validation(String),
+    /// The request clashes with what is already stored
+    #[display("{_0}")]
+    Conflict(String),
+    #[display("Not logged in")]
+    Unauthorized,
+    #[display("Not allowed")]
+    Forbidden,
+    /// The problem isn't taking submissions right now
+    #[display("{_0}")]
+    Closed(String),
+    #[display("Upload exceeds {limit} of {max}")]
+    LimitExceeded { limit: &'static str, max: u64 },
+    #[display("{filename} does not have an accepted file extension")]
+    DisallowedFile { filename: String },
+    #[display("Storage error: {_0}")]
+    Storage(std::io::Error),
+    #[display("Database error: {_0}")]
+    Database(turso::Error),
+    #[display("{_0}")]
+    Internal(String),
+}
+
+impl SubmError {
+    /// Stable identifier of the error kind for clients to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            SubmError::NotFound(_) => "not_found",
+            SubmError::BadRequest(_) => "bad_request",
+            SubmError::Validation(_) => "validation",
+            SubmError::Conflict(_) => "conflict",
+            SubmError::Unauthorized => "unauthorized",
+            SubmError::Forbidden => "forbidden",
+            SubmError::Closed(_) => "closed",
+            SubmError::LimitExceeded { .. } => "limit_exceeded",
+            SubmError::DisallowedFile { .. } => "disallowed_file",
+            SubmError::Storage(_) => "storage",
+            SubmError::Database(_) => "database",
+            SubmError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl From<turso::Error> for SubmError {
+    fn from(e: turso::Error) -> Self {
+        SubmError::Database(e)
+    }
+}
+
+impl From<std::io::Error> for SubmError {
+    fn from(e: std::io::Error) -> Self {
+        SubmError::Storage(e)
+    }
 }
 
 impl ResponseError for SubmError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SubmError::NotFound(_) => StatusCode::NOT_FOUND,
+            SubmError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            SubmError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            SubmError::Conflict(_) => StatusCode::CONFLICT,
+            SubmError::Unauthorized => StatusCode::UNAUTHORIZED,
+            SubmError::Forbidden => StatusCode::FORBIDDEN,
+            SubmError::Closed(_) => StatusCode::FORBIDDEN,
+            SubmError::LimitExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            SubmError::DisallowedFile { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            SubmError::Storage(_) | SubmError::Database(_) | SubmError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+
+        // Server-side details are logged rather than shown to clients
+        let message = if status.is_server_error() {
+            error!("{}", self);
+            "Internal server error".to_string()
+        } else {
+            self.to_string()
+        };
 
+        let mut body = serde_json::json!({ "error": message, "code": self.code() });
+        match self {
+            SubmError::LimitExceeded { limit, max } => {
+                body["limit"] = serde_json::json!(limit);
+                body["max"] = serde_json::json!(max);
+            }
+            SubmError::DisallowedFile { .. } => {
+                body["limit"] = serde_json::json!("allowed_extensions");
+            }
+            _ => {}
+        }
+        HttpResponse::build(status).json(body)
+    }
 }
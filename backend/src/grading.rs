@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+
+use crate::db::{CreateFeedback, NewCriterion, RubricCriterion, SubmissionState};
+use crate::error::SubmError;
+
+/// Score of a problem graded only as pass or fail
+pub const DEFAULT_MAX_SCORE: i64 = 1;
+
+/// What a feedback decides about a submission
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Verdict {
+    /// 1 if the submission is accepted, 0 otherwise
+    pub grade: i64,
+    pub score: Option<i64>,
+    pub state: SubmissionState,
+}
+
+/// The problem's maximum score, which has to agree with its rubric if it has one
+pub fn resolve_max_score(
+    max_score: Option<i64>,
+    rubric: &[NewCriterion],
+) -> Result<i64, SubmError> {
+    for criterion in rubric {
+        if criterion.name.trim().is_empty() {
+            return Err(SubmError::Validation(
+                "Rubric criteria must have a name".to_string(),
+            ));
+        }
+        if criterion.points <= 0 {
+            return Err(SubmError::Validation(format!(
+                "Criterion {} must be worth a positive number of points",
+                criterion.name
+            )));
+        }
+    }
+
+    if rubric.is_empty() {
+        let max_score = max_score.unwrap_or(DEFAULT_MAX_SCORE);
+        if max_score <= 0 {
+            return Err(SubmError::Validation(
+                "Maximum score must be positive".to_string(),
+            ));
+        }
+        return Ok(max_score);
+    }
+
+    let total = rubric.iter().map(|criterion| criterion.points).sum();
+    match max_score {
+        Some(max_score) if max_score != total => Err(SubmError::Validation(format!(
+            "Maximum score {max_score} doesn't match the rubric total of {total}"
+        ))),
+        _ => Ok(total),
+    }
+}
+
+/// Checks a feedback against the problem's grading and works out the verdict
+/// to store for it
+pub fn score_feedback(
+    feedback: &CreateFeedback,
+    max_score: i64,
+    rubric: &[RubricCriterion],
+) -> Result<Verdict, SubmError> {
+    let mut score = feedback.score;
+
+    if !feedback.points.is_empty() {
+        if rubric.is_empty() {
+            return Err(SubmError::Validation(
+                "Problem has no rubric to give points for".to_string(),
+            ));
+        }
+
+        let mut given = HashMap::new();
+        for entry in &feedback.points {
+            if given.insert(entry.criterion, entry.points).is_some() {
+                return Err(SubmError::Validation(format!(
+                    "Criterion {} is scored more than once",
+                    entry.criterion
+                )));
+            }
+        }
+
+        let mut total = 0;
+        for criterion in rubric {
+            let Some(points) = given.remove(&criterion.id) else {
+                return Err(SubmError::Validation(format!(
+                    "Criterion {} is not scored",
+                    criterion.name
+                )));
+            };
+            if !(0..=criterion.points).contains(&points) {
+                return Err(SubmError::Validation(format!(
+                    "Criterion {} is worth 0 to {} points",
+                    criterion.name, criterion.points
+                )));
+            }
+            total += points;
+        }
+        if let Some(unknown) = given.keys().next() {
+            return Err(SubmError::Validation(format!(
+                "Criterion {unknown} is not part of the rubric"
+            )));
+        }
+
+        match score {
+            Some(score) if score != total => {
+                return Err(SubmError::Validation(format!(
+                    "Score {score} doesn't match the points total of {total}"
+                )));
+            }
+            _ => score = Some(total),
+        }
+    }
+
+    if score.is_some_and(|score| !(0..=max_score).contains(&score)) {
+        return Err(SubmError::Validation(format!(
+            "Score must be between 0 and {max_score}"
+        )));
+    }
+
+    let grade = match feedback.grade {
+        Some(grade @ (0 | 1)) => Some(grade),
+        Some(_) => {
+            return Err(SubmError::Validation(
+                "Grade must be either 0 or 1".to_string(),
+            ));
+        }
+        None => None,
+    };
+
+    let state = match (feedback.state, grade, score) {
+        (Some(state), Some(grade), _) if (state == SubmissionState::Accepted) != (grade == 1) => {
+            return Err(SubmError::Validation(format!(
+                "Grade {grade} contradicts state {}",
+                state.as_str()
+            )));
+        }
+        (Some(state), _, _) => state,
+        (None, Some(grade), _) => accepted_if(grade == 1),
+        (None, None, Some(score)) => accepted_if(score == max_score),
+        (None, None, None) => {
+            return Err(SubmError::Validation(
+                "Feedback needs a state, a grade or a score".to_string(),
+            ));
+        }
+    };
+    if state == SubmissionState::Pending {
+        return Err(SubmError::Validation(
+            "Feedback can't make a submission pending".to_string(),
+        ));
+    }
+
+    Ok(Verdict {
+        grade: (state == SubmissionState::Accepted) as i64,
+        score,
+        state,
+    })
+}
+
+fn accepted_if(accepted: bool) -> SubmissionState {
+    if accepted {
+        SubmissionState::Accepted
+    } else {
+        SubmissionState::Rejected
+    }
+}
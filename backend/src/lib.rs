@@ -1,3 +1,11 @@
 pub mod api;
+pub mod auth;
+pub mod blobstore;
 pub mod db;
 pub mod error;
+pub mod grading;
+pub mod limits;
+pub mod migrations;
+pub mod paths;
+pub mod pool;
+pub mod revisions;
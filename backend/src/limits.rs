@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use crate::db::Problem;
+
+pub const DEFAULT_MAX_FILES: u64 = 20;
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_MAX_TOTAL_SIZE: u64 = 50 * 1024 * 1024;
+
+/// Limits applied to a single submission upload
+#[derive(Clone, Debug)]
+pub struct UploadLimits {
+    pub max_files: u64,
+    pub max_file_size: u64,
+    pub max_total_size: u64,
+    /// Accepted file extensions without the dot, any extension if empty
+    pub allowed_extensions: Vec<String>,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            max_files: DEFAULT_MAX_FILES,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            allowed_extensions: Vec::new(),
+        }
+    }
+}
+
+impl UploadLimits {
+    /// These limits with the problem's own overrides applied on top. Overrides
+    /// below 1, which only problems saved before they were refused can have,
+    /// are ignored.
+    pub fn for_problem(&self, problem: &Problem) -> UploadLimits {
+        let overrides = &problem.limits;
+        let positive = |n: i64| u64::try_from(n).ok().filter(|&n| n > 0);
+        UploadLimits {
+            max_files: overrides.max_files.and_then(positive).unwrap_or(self.max_files),
+            max_file_size: overrides
+                .max_file_size
+                .and_then(positive)
+                .unwrap_or(self.max_file_size),
+            max_total_size: overrides
+                .max_total_size
+                .and_then(positive)
+                .unwrap_or(self.max_total_size),
+            allowed_extensions: overrides
+                .allowed_extensions
+                .clone()
+                .unwrap_or_else(|| self.allowed_extensions.clone()),
+        }
+    }
+
+    pub fn allows_file(&self, filename: &str) -> bool {
+        if self.allowed_extensions.is_empty() {
+            return true;
+        }
+
+        let Some(ext) = Path::new(filename).extension().and_then(|e| e.to_str()) else {
+            return false;
+        };
+        self.allowed_extensions
+            .iter()
+            .any(|allowed| allowed.trim_start_matches('.').eq_ignore_ascii_case(ext))
+    }
+}
@@ -1,34 +1,160 @@
 pub mod api;
+pub mod auth;
+pub mod blobstore;
 pub mod db;
 pub mod error;
+pub mod grading;
+pub mod limits;
+pub mod migrations;
+pub mod paths;
+pub mod pool;
+pub mod revisions;
 
 use actix_files::{Files, NamedFile};
 use actix_web::{App, HttpServer, Result, middleware, web};
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::sync::{Mutex};
+use std::time::Duration;
 
 use crate::api::*;
+use crate::blobstore::{BlobStore, LocalStore, S3Config, S3Store};
 use crate::db::*;
+use crate::limits::UploadLimits;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[arg(long, default_value = "127.0.0.1")]
     host: String,
 
     #[arg(short, long, default_value_t = 8080)]
     port: u16,
 
-    #[arg(short, long, default_value = "file.sqlite")]
+    #[arg(short, long, global = true, default_value = "file.sqlite")]
     database: String,
 
-    #[arg(short, long, default_value = "uploads")]
+    /// Where uploads are staged, and stored too unless `--storage` says otherwise
+    #[arg(short, long, global = true, default_value = "uploads")]
     uploads: String,
 
+    #[command(flatten)]
+    storage: StorageArgs,
+
     #[arg(short, long, default_value = "./static")]
     static_dir: String,
+
+    /// Most files a single submission may contain
+    #[arg(long, default_value_t = limits::DEFAULT_MAX_FILES)]
+    max_files: u64,
+
+    /// Largest accepted file in bytes
+    #[arg(long, default_value_t = limits::DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// Largest accepted submission in bytes, all files and the comment together
+    #[arg(long, default_value_t = limits::DEFAULT_MAX_TOTAL_SIZE)]
+    max_total_size: u64,
+
+    /// Comma-separated file extensions to accept, e.g. `py,rs,txt`; any if unset
+    #[arg(long, value_delimiter = ',')]
+    allowed_extensions: Vec<String>,
+
+    /// Check every downloaded file against its hash while sending it
+    #[arg(long)]
+    verify_downloads: bool,
+
+    /// Collect garbage in the file store every this many minutes
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    gc_interval: Option<u64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Storage {
+    /// Files in the uploads directory
+    Local,
+    /// Objects in an S3-compatible bucket
+    S3,
+}
+
+#[derive(clap::Args)]
+struct StorageArgs {
+    /// Where uploaded files are kept
+    #[arg(long, global = true, value_enum, default_value_t = Storage::Local)]
+    storage: Storage,
+
+    /// Base URL of the S3-compatible service, e.g. `http://localhost:9000`
+    #[arg(long, global = true)]
+    s3_endpoint: Option<String>,
+
+    #[arg(long, global = true, default_value = "us-east-1")]
+    s3_region: String,
+
+    #[arg(long, global = true)]
+    s3_bucket: Option<String>,
+
+    /// Put in front of every object key
+    #[arg(long, global = true, default_value = "")]
+    s3_prefix: String,
+
+    #[arg(long, global = true, env = "S3_ACCESS_KEY", hide_env_values = true)]
+    s3_access_key: Option<String>,
+
+    #[arg(long, global = true, env = "S3_SECRET_KEY", hide_env_values = true)]
+    s3_secret_key: Option<String>,
+}
+
+impl StorageArgs {
+    fn blob_store(self, uploads: &str) -> std::io::Result<Arc<dyn BlobStore>> {
+        match self.storage {
+            Storage::Local => Ok(Arc::new(LocalStore::new(uploads))),
+            Storage::S3 => {
+                let required = |value: Option<String>, flag: &str| {
+                    value.ok_or_else(|| {
+                        std::io::Error::other(format!("--{} is required with --storage s3", flag))
+                    })
+                };
+                Ok(Arc::new(S3Store::new(S3Config {
+                    endpoint: required(self.s3_endpoint, "s3-endpoint")?,
+                    region: self.s3_region,
+                    bucket: required(self.s3_bucket, "s3-bucket")?,
+                    access_key: required(self.s3_access_key, "s3-access-key")?,
+                    secret_key: required(self.s3_secret_key, "s3-secret-key")?,
+                    prefix: self.s3_prefix,
+                })))
+            }
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a user account and exit
+    AddUser {
+        #[arg(long)]
+        name: String,
+
+        #[arg(long)]
+        password: String,
+
+        /// Give the account the teacher role instead of student
+        #[arg(long)]
+        teacher: bool,
+    },
+    /// Apply pending database migrations and exit
+    Migrate,
+    /// Remove uploaded files nothing references anymore and exit
+    Gc {
+        /// Only report what would be removed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check every uploaded file against its hash and the database, exiting
+    /// with an error if anything is wrong
+    Fsck,
 }
 
 // Handler for SPA routing - serves index.html for any non-API route
@@ -39,9 +165,120 @@ async fn spa_handler(static_dir: web::Data<String>) -> Result<NamedFile> {
     Ok(NamedFile::open(path)?)
 }
 
+async fn run_command(state: &SubmDb, command: Command) -> std::io::Result<()> {
+    match command {
+        Command::Migrate => {
+            let applied = state
+                .migrate()
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            for migration in &applied {
+                println!("Applied migration {}: {}", migration.version, migration.name);
+            }
+            let version = state
+                .schema_version()
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            println!("Schema is at version {}", version);
+        }
+        Command::AddUser { name, password, teacher } => {
+            state.init().await;
+            let role = if teacher { Role::Teacher } else { Role::Student };
+            let user = CreateUser { name: name.clone(), password, role };
+            match state.create_user(user).await {
+                Ok(id) => println!("Created {} {} with ID {}", role.as_str(), name, id),
+                Err(e) => return Err(std::io::Error::other(e.to_string())),
+            }
+        }
+        Command::Gc { dry_run } => {
+            state.init().await;
+            let report = state
+                .collect_garbage(dry_run, GC_GRACE_PERIOD)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            for hash in &report.orphan_blobs {
+                println!("Orphaned blob {}", hash);
+            }
+            for id in &report.orphan_files {
+                println!("Orphaned file row {}", id);
+            }
+            let verb = if dry_run { "Would reclaim" } else { "Reclaimed" };
+            println!(
+                "{} {} bytes from {} blobs, {} file rows and {} stale staged uploads",
+                verb,
+                report.reclaimable_bytes,
+                report.orphan_blobs.len(),
+                report.orphan_files.len(),
+                report.stale_staged
+            );
+        }
+        Command::Fsck => {
+            state.init().await;
+            let report = state
+                .fsck()
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            for hash in &report.missing {
+                println!("Missing blob {}", hash);
+            }
+            for hash in &report.corrupt {
+                println!("Corrupt blob {}", hash);
+            }
+            for id in &report.unattached_files {
+                println!("File row {} is not attached to any submission", id);
+            }
+            for attachment in &report.dangling_attachments {
+                println!(
+                    "Attachment of file {:?} to submission {:?} points at nothing",
+                    attachment.file, attachment.submission
+                );
+            }
+            println!("Checked {} blobs", report.checked);
+            if !report.is_clean() {
+                return Err(std::io::Error::other("Storage has problems"));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Collects garbage every `minutes` for as long as the server runs
+fn spawn_gc(state: Arc<SubmDb>, minutes: u64) {
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(minutes * 60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = state.collect_garbage(false, GC_GRACE_PERIOD).await {
+                log::error!("Garbage collection failed: {}", e);
+            }
+        }
+    });
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let Args { host, port, database, uploads, static_dir } = Args::parse();
+    let Args {
+        command,
+        host,
+        port,
+        database,
+        uploads,
+        storage,
+        static_dir,
+        max_files,
+        max_file_size,
+        max_total_size,
+        allowed_extensions,
+        verify_downloads,
+        gc_interval,
+    } = Args::parse();
+
+    let store = storage.blob_store(&uploads)?;
+
+    if let Some(command) = command {
+        let state = SubmDb::new(&database, uploads).await.with_blob_store(store);
+        return run_command(&state, command).await;
+    }
 
     println!("Starting server on {}:{}", &host, &port);
     println!("Database: {}", &database);
@@ -50,9 +287,24 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    let state = SubmDb::new(&database, uploads).await;
+    let state = SubmDb::new(&database, uploads)
+        .await
+        .with_blob_store(store)
+        .with_blob_verification(verify_downloads);
     state.init().await;
-    let state = Arc::new(Mutex::new(state));
+    let state = Arc::new(state);
+
+    if let Some(minutes) = gc_interval {
+        println!("Collecting garbage every {} minutes", minutes);
+        spawn_gc(state.clone(), minutes);
+    }
+
+    let upload_limits = UploadLimits {
+        max_files,
+        max_file_size,
+        max_total_size,
+        allowed_extensions,
+    };
 
     let assets_path = format!("{}/assets", &static_dir);
 
@@ -60,15 +312,44 @@ async fn main() -> std::io::Result<()> {
         App::new()
             .app_data(web::Data::new(state.clone()))
             .app_data(web::Data::new(static_dir.clone()))
+            .app_data(web::Data::new(upload_limits.clone()))
+            .app_data(json_config())
+            .app_data(path_config())
+            .app_data(query_config())
             // API routes - these take priority over static files
             .service(index)
+            .service(login)
+            .service(logout)
+            .service(get_me)
+            .service(create_user)
+            .service(create_course)
+            .service(get_courses)
+            .service(get_course)
+            .service(add_course_member)
+            .service(get_course_members)
+            .service(get_course_problems)
+            .service(get_course_submissions)
             .service(create_problem)
             .service(get_problems)
+            // Before get_problem, which would take "stats" for an ID
+            .service(get_problem_stats)
             .service(get_problem)
+            .service(get_problem_revisions)
+            .service(get_problem_revision_diff)
+            .service(get_extensions)
+            .service(grant_extension)
+            .service(revoke_extension)
+            .service(update_problem)
+            .service(patch_problem)
+            .service(delete_problem)
             .service(create_submission)
             .service(get_submissions)
             .service(get_submission)
+            .service(get_submission_tree)
             .service(create_feedback)
+            .service(edit_feedback)
+            .service(retract_feedback)
+            .service(get_feedback_edits)
             .service(get_file)
             // Serve static files from the built frontend
             .service(Files::new("/assets", &assets_path))
@@ -0,0 +1,447 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use turso::Connection;
+
+use log::info;
+
+/// Data changes plain SQL can't express, run after a migration's statements
+pub type Backfill =
+    for<'a> fn(&'a Connection) -> Pin<Box<dyn Future<Output = Result<(), turso::Error>> + Send + 'a>>;
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    pub backfill: Option<Backfill>,
+}
+
+/// Every schema change, in the order they were introduced. Only ever append to
+/// this list; a migration that has shipped must not be edited, since databases
+/// that already applied it won't see the change.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial schema",
+        // Databases from before migrations existed already have these tables
+        sql: "
+            CREATE TABLE IF NOT EXISTS problem (
+                id INTEGER PRIMARY KEY,
+                name TEXT,
+                desc TEXT
+            );
+            CREATE TABLE IF NOT EXISTS file (
+                id INTEGER PRIMARY KEY,
+                name TEXT,
+                hash TEXT
+            );
+            CREATE TABLE IF NOT EXISTS submission (
+                id INTEGER PRIMARY KEY,
+                comment TEXT,
+                problem INTEGER,
+                FOREIGN KEY (problem) REFERENCES problem(id)
+            );
+            CREATE TABLE IF NOT EXISTS attachment (
+                submission INTEGER,
+                file INTEGER,
+                FOREIGN KEY (submission) REFERENCES submission(id),
+                FOREIGN KEY (file) REFERENCES file(id)
+            );
+            CREATE TABLE IF NOT EXISTS feedback (
+                id INTEGER PRIMARY KEY,
+                submission INTEGER,
+                grade INTEGER CHECK (grade IN (0, 1)),
+                message TEXT,
+                FOREIGN KEY (submission) REFERENCES submission(id)
+            );
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 2,
+        name: "user accounts",
+        sql: "
+            CREATE TABLE user (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                password_hash TEXT NOT NULL,
+                role TEXT NOT NULL CHECK (role IN ('teacher', 'student'))
+            );
+            CREATE TABLE session (
+                token TEXT PRIMARY KEY,
+                user INTEGER NOT NULL,
+                FOREIGN KEY (user) REFERENCES user(id)
+            );
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 3,
+        name: "submission authors",
+        // Submissions made before accounts existed are left without an author
+        sql: "
+            ALTER TABLE submission ADD COLUMN author INTEGER REFERENCES user(id);
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 4,
+        name: "courses",
+        // Problems that predate courses are collected into a default one; the
+        // table is new, so that course is the first and only one
+        sql: "
+            CREATE TABLE course (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL
+            );
+            CREATE TABLE course_member (
+                course INTEGER NOT NULL,
+                user INTEGER NOT NULL,
+                PRIMARY KEY (course, user),
+                FOREIGN KEY (course) REFERENCES course(id),
+                FOREIGN KEY (user) REFERENCES user(id)
+            );
+            ALTER TABLE problem ADD COLUMN course INTEGER REFERENCES course(id);
+            INSERT INTO course (id, name) SELECT DISTINCT 1, 'Default course' FROM problem;
+            UPDATE problem SET course = 1 WHERE course IS NULL;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 5,
+        name: "per-problem upload limits",
+        sql: "
+            ALTER TABLE problem ADD COLUMN max_files INTEGER;
+            ALTER TABLE problem ADD COLUMN max_file_size INTEGER;
+            ALTER TABLE problem ADD COLUMN max_total_size INTEGER;
+            ALTER TABLE problem ADD COLUMN allowed_extensions TEXT;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 6,
+        name: "problem deletion",
+        sql: "
+            ALTER TABLE problem ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 7,
+        name: "problem revisions",
+        // Existing problems start their history from the current statement,
+        // its revision sharing the problem's ID; what older submissions
+        // answered is unknown, so they get no revision
+        sql: "
+            CREATE TABLE problem_revision (
+                id INTEGER PRIMARY KEY,
+                problem INTEGER NOT NULL,
+                number INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                desc TEXT NOT NULL,
+                UNIQUE (problem, number),
+                FOREIGN KEY (problem) REFERENCES problem(id)
+            );
+            ALTER TABLE problem ADD COLUMN revision INTEGER REFERENCES problem_revision(id);
+            ALTER TABLE submission ADD COLUMN revision INTEGER REFERENCES problem_revision(id);
+            INSERT INTO problem_revision (id, problem, number, name, desc)
+                SELECT id, id, 1, COALESCE(name, ''), COALESCE(desc, '') FROM problem;
+            UPDATE problem SET revision = id;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 8,
+        name: "problem deadlines",
+        // Times are stored as unix seconds
+        sql: "
+            ALTER TABLE problem ADD COLUMN opens_at INTEGER;
+            ALTER TABLE problem ADD COLUMN soft_deadline INTEGER;
+            ALTER TABLE problem ADD COLUMN hard_deadline INTEGER;
+            ALTER TABLE submission ADD COLUMN late INTEGER NOT NULL DEFAULT 0;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 9,
+        name: "deadline extensions",
+        sql: "
+            CREATE TABLE extension (
+                problem INTEGER NOT NULL,
+                student INTEGER NOT NULL,
+                due_at INTEGER NOT NULL,
+                PRIMARY KEY (problem, student),
+                FOREIGN KEY (problem) REFERENCES problem(id),
+                FOREIGN KEY (student) REFERENCES user(id)
+            );
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 10,
+        name: "scores and rubrics",
+        // Existing problems keep pass/fail grading, worth one point
+        sql: "
+            ALTER TABLE problem ADD COLUMN max_score INTEGER NOT NULL DEFAULT 1;
+            CREATE TABLE rubric_criterion (
+                id INTEGER PRIMARY KEY,
+                problem INTEGER NOT NULL,
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                points INTEGER NOT NULL,
+                FOREIGN KEY (problem) REFERENCES problem(id)
+            );
+            ALTER TABLE feedback ADD COLUMN score INTEGER;
+            CREATE TABLE feedback_points (
+                feedback INTEGER NOT NULL,
+                criterion INTEGER NOT NULL,
+                points INTEGER NOT NULL,
+                PRIMARY KEY (feedback, criterion),
+                FOREIGN KEY (feedback) REFERENCES feedback(id),
+                FOREIGN KEY (criterion) REFERENCES rubric_criterion(id)
+            );
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 11,
+        name: "submission states",
+        // Graded submissions take the verdict of their latest feedback
+        sql: "
+            ALTER TABLE feedback ADD COLUMN state TEXT NOT NULL DEFAULT 'rejected';
+            UPDATE feedback SET state = 'accepted' WHERE grade = 1;
+            ALTER TABLE submission ADD COLUMN state TEXT NOT NULL DEFAULT 'pending';
+        ",
+        backfill: Some(|conn| Box::pin(submission_states(conn))),
+    },
+    Migration {
+        version: 12,
+        name: "feedback authorship",
+        // Who wrote older feedback and when isn't known
+        sql: "
+            ALTER TABLE feedback ADD COLUMN author INTEGER REFERENCES user(id);
+            ALTER TABLE feedback ADD COLUMN created_at INTEGER;
+            ALTER TABLE feedback ADD COLUMN updated_at INTEGER;
+            ALTER TABLE feedback ADD COLUMN retracted_at INTEGER;
+            CREATE TABLE feedback_edit (
+                id INTEGER PRIMARY KEY,
+                feedback INTEGER NOT NULL,
+                message TEXT,
+                replaced_at INTEGER NOT NULL,
+                FOREIGN KEY (feedback) REFERENCES feedback(id)
+            );
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 13,
+        name: "timestamps",
+        // Rows from before this stay without times
+        sql: "
+            ALTER TABLE user ADD COLUMN created_at INTEGER;
+            ALTER TABLE user ADD COLUMN updated_at INTEGER;
+            ALTER TABLE course ADD COLUMN created_at INTEGER;
+            ALTER TABLE course ADD COLUMN updated_at INTEGER;
+            ALTER TABLE problem ADD COLUMN created_at INTEGER;
+            ALTER TABLE problem ADD COLUMN updated_at INTEGER;
+            ALTER TABLE submission ADD COLUMN created_at INTEGER;
+            ALTER TABLE submission ADD COLUMN updated_at INTEGER;
+            ALTER TABLE file ADD COLUMN created_at INTEGER;
+            ALTER TABLE file ADD COLUMN updated_at INTEGER;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 14,
+        name: "attachment names",
+        // Earlier attachments can only get the name their blob was first
+        // stored under; `file.name` stays but is no longer read
+        sql: "
+            ALTER TABLE attachment ADD COLUMN name TEXT;
+        ",
+        backfill: Some(|conn| Box::pin(attachment_names(conn))),
+    },
+    Migration {
+        version: 15,
+        name: "attachment paths",
+        // Files submitted so far sit at the top of their submission
+        sql: "
+            ALTER TABLE attachment ADD COLUMN path TEXT;
+            UPDATE attachment SET path = name;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 16,
+        name: "more timestamps",
+        // Rows from before this stay without times, except edits, which
+        // always recorded when they were made. Turso can't load the schema
+        // of problem_revision once columns are added to it, so it is
+        // rebuilt instead.
+        sql: "
+            ALTER TABLE attachment ADD COLUMN created_at INTEGER;
+            ALTER TABLE attachment ADD COLUMN updated_at INTEGER;
+            CREATE TABLE problem_revision_new (
+                id INTEGER PRIMARY KEY,
+                problem INTEGER NOT NULL,
+                number INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                desc TEXT NOT NULL,
+                created_at INTEGER,
+                updated_at INTEGER,
+                UNIQUE (problem, number),
+                FOREIGN KEY (problem) REFERENCES problem(id)
+            );
+            INSERT INTO problem_revision_new (id, problem, number, name, desc)
+                SELECT id, problem, number, name, desc FROM problem_revision;
+            DROP TABLE problem_revision;
+            ALTER TABLE problem_revision_new RENAME TO problem_revision;
+            ALTER TABLE extension ADD COLUMN created_at INTEGER;
+            ALTER TABLE extension ADD COLUMN updated_at INTEGER;
+            ALTER TABLE feedback_edit ADD COLUMN created_at INTEGER;
+            ALTER TABLE feedback_edit ADD COLUMN updated_at INTEGER;
+            ALTER TABLE session ADD COLUMN created_at INTEGER;
+            ALTER TABLE session ADD COLUMN updated_at INTEGER;
+            UPDATE feedback_edit SET created_at = replaced_at, updated_at = replaced_at;
+        ",
+        backfill: None,
+    },
+    Migration {
+        version: 17,
+        name: "timestamps everywhere",
+        // A row counts as updated when it's created, which rows that have a
+        // creation time catch up on. Tables keyed by two columns get rebuilt,
+        // for the same reason problem_revision did.
+        sql: "
+            CREATE TABLE course_member_new (
+                course INTEGER NOT NULL,
+                user INTEGER NOT NULL,
+                created_at INTEGER,
+                updated_at INTEGER,
+                PRIMARY KEY (course, user),
+                FOREIGN KEY (course) REFERENCES course(id),
+                FOREIGN KEY (user) REFERENCES user(id)
+            );
+            INSERT INTO course_member_new (course, user) SELECT course, user FROM course_member;
+            DROP TABLE course_member;
+            ALTER TABLE course_member_new RENAME TO course_member;
+            CREATE TABLE feedback_points_new (
+                feedback INTEGER NOT NULL,
+                criterion INTEGER NOT NULL,
+                points INTEGER NOT NULL,
+                created_at INTEGER,
+                updated_at INTEGER,
+                PRIMARY KEY (feedback, criterion),
+                FOREIGN KEY (feedback) REFERENCES feedback(id),
+                FOREIGN KEY (criterion) REFERENCES rubric_criterion(id)
+            );
+            INSERT INTO feedback_points_new (feedback, criterion, points)
+                SELECT feedback, criterion, points FROM feedback_points;
+            DROP TABLE feedback_points;
+            ALTER TABLE feedback_points_new RENAME TO feedback_points;
+            ALTER TABLE rubric_criterion ADD COLUMN created_at INTEGER;
+            ALTER TABLE rubric_criterion ADD COLUMN updated_at INTEGER;
+            UPDATE problem SET updated_at = created_at WHERE updated_at IS NULL;
+            UPDATE submission SET updated_at = created_at WHERE updated_at IS NULL;
+            UPDATE feedback SET updated_at = created_at WHERE updated_at IS NULL;
+        ",
+        backfill: None,
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+pub async fn current_version(conn: &Connection) -> Result<i64, turso::Error> {
+    conn.execute(
+            "CREATE TABLE IF NOT EXISTS schema_version (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL
+            )",
+            (),
+        )
+        .await?;
+
+    let mut rows = conn
+        .query("SELECT COALESCE(MAX(version), 0) FROM schema_version", ())
+        .await?;
+    match rows.next().await? {
+        Some(row) => row.get(0),
+        None => Ok(0),
+    }
+}
+
+/// Applies every migration newer than the database, each in its own
+/// transaction, and returns the ones that were applied
+pub async fn migrate(conn: &Connection) -> Result<Vec<&'static Migration>, turso::Error> {
+    let current = current_version(conn).await?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        info!("Applying migration {}: {}", migration.version, migration.name);
+
+        conn.execute("BEGIN", ()).await?;
+        if let Err(e) = apply(conn, migration).await {
+            conn.execute("ROLLBACK", ()).await?;
+            return Err(e);
+        }
+        conn.execute("COMMIT", ()).await?;
+
+        applied.push(migration);
+    }
+    Ok(applied)
+}
+
+async fn apply(conn: &Connection, migration: &Migration) -> Result<(), turso::Error> {
+    conn.execute_batch(migration.sql).await?;
+    if let Some(backfill) = migration.backfill {
+        backfill(conn).await?;
+    }
+    conn.execute(
+            "INSERT INTO schema_version (version, name) VALUES (?, ?)",
+            (migration.version, migration.name),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Gives every submission the state of its latest feedback
+async fn submission_states(conn: &Connection) -> Result<(), turso::Error> {
+    let mut latest = HashMap::new();
+    let mut rows = conn
+        .query(
+            "SELECT submission, state FROM feedback WHERE submission IS NOT NULL ORDER BY id",
+            (),
+        )
+        .await?;
+    while let Some(row) = rows.next().await? {
+        latest.insert(row.get::<i64>(0)?, row.get::<String>(1)?);
+    }
+    drop(rows);
+
+    for (submission, state) in latest {
+        conn.execute(
+                "UPDATE submission SET state = ? WHERE id = ?",
+                (state, submission),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Names every attachment after the file it points at
+async fn attachment_names(conn: &Connection) -> Result<(), turso::Error> {
+    let mut files = Vec::new();
+    let mut rows = conn.query("SELECT id, name FROM file", ()).await?;
+    while let Some(row) = rows.next().await? {
+        files.push((row.get::<i64>(0)?, row.get::<Option<String>>(1)?));
+    }
+    drop(rows);
+
+    for (file, name) in files {
+        conn.execute("UPDATE attachment SET name = ? WHERE file = ?", (name, file))
+            .await?;
+    }
+    Ok(())
+}
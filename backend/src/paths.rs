@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::collections::HashSet;
+
+use crate::db::FileInfo;
+use crate::error::SubmError;
+
+/// Longest relative path accepted for a submitted file, in bytes
+pub const MAX_PATH_LENGTH: usize = 1024;
+
+/// Turns the name a file was uploaded under into a clean relative path:
+/// `/` separated, without empty or `.` components. Backslashes count as
+/// separators and leading ones are dropped, but `..` is refused, since
+/// whoever unpacks the submission later could be tricked into writing
+/// outside of it.
+pub fn sanitize_path(raw: &str) -> Result<String, SubmError> {
+    let mut parts = Vec::new();
+    for part in raw.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                return Err(SubmError::Validation(format!(
+                    "{} points outside the submission",
+                    raw
+                )));
+            }
+            part if part.chars().any(char::is_control) => {
+                return Err(SubmError::Validation(format!(
+                    "{} contains control characters",
+                    raw.escape_debug()
+                )));
+            }
+            part => parts.push(part),
+        }
+    }
+
+    let path = parts.join("/");
+    if path.is_empty() {
+        return Err(SubmError::Validation("Every file needs a name".to_string()));
+    }
+    if path.len() > MAX_PATH_LENGTH {
+        return Err(SubmError::Validation(format!(
+            "Path is longer than {} bytes",
+            MAX_PATH_LENGTH
+        )));
+    }
+    Ok(path)
+}
+
+/// Last component of a sanitized path
+pub fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Names the files that came without a name `unnamed-1`, `unnamed-2` and so
+/// on, skipping names other files already have
+pub fn name_unnamed<T>(files: Vec<(Option<String>, T)>) -> Vec<(String, T)> {
+    let mut taken: HashSet<String> = files.iter().filter_map(|(path, _)| path.clone()).collect();
+    let mut n = 0;
+    files
+        .into_iter()
+        .map(|(path, file)| {
+            let path = path.unwrap_or_else(|| loop {
+                n += 1;
+                let name = format!("unnamed-{}", n);
+                if taken.insert(name.clone()) {
+                    break name;
+                }
+            });
+            (path, file)
+        })
+        .collect()
+}
+
+/// Makes sure sanitized paths can all live in one tree: no two files at the
+/// same path, and no file where another one needs a directory
+pub fn check_paths<'a>(paths: impl IntoIterator<Item = &'a str>) -> Result<(), SubmError> {
+    let paths: Vec<&str> = paths.into_iter().collect();
+    let mut files = HashSet::new();
+    for path in &paths {
+        if !files.insert(*path) {
+            return Err(SubmError::Validation(format!("More than one file at {}", path)));
+        }
+    }
+    for path in &paths {
+        for (end, _) in path.match_indices('/') {
+            if files.contains(&path[..end]) {
+                return Err(SubmError::Validation(format!(
+                    "{} is a file, so {} can't be inside it",
+                    &path[..end],
+                    path
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// One entry of a submission's file tree
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TreeNode {
+    Directory {
+        name: String,
+        children: Vec<TreeNode>,
+    },
+    File {
+        name: String,
+        path: String,
+        id: i64,
+        hash: String,
+    },
+}
+
+impl TreeNode {
+    fn name(&self) -> &str {
+        match self {
+            TreeNode::Directory { name, .. } | TreeNode::File { name, .. } => name,
+        }
+    }
+}
+
+/// Arranges a submission's files by their paths. Each level lists its
+/// directories first, then its files, both by name.
+pub fn file_tree(files: &[FileInfo]) -> Vec<TreeNode> {
+    let mut root = Vec::new();
+    for file in files {
+        let dirs: Vec<&str> = file.path.split('/').collect();
+        insert(&mut root, &dirs[..dirs.len() - 1], file);
+    }
+    sort(&mut root);
+    root
+}
+
+fn insert(nodes: &mut Vec<TreeNode>, dirs: &[&str], file: &FileInfo) {
+    let Some((dir, rest)) = dirs.split_first() else {
+        nodes.push(TreeNode::File {
+            name: file.name.clone(),
+            path: file.path.clone(),
+            id: file.id,
+            hash: file.hash.clone(),
+        });
+        return;
+    };
+
+    let existing = nodes
+        .iter()
+        .position(|node| matches!(node, TreeNode::Directory { name, .. } if name == dir));
+    let index = existing.unwrap_or_else(|| {
+        nodes.push(TreeNode::Directory {
+            name: dir.to_string(),
+            children: Vec::new(),
+        });
+        nodes.len() - 1
+    });
+    if let TreeNode::Directory { children, .. } = &mut nodes[index] {
+        insert(children, rest, file);
+    }
+}
+
+fn sort(nodes: &mut [TreeNode]) {
+    nodes.sort_by(|a, b| {
+        let is_file = |node: &TreeNode| matches!(node, TreeNode::File { .. });
+        is_file(a)
+            .cmp(&is_file(b))
+            .then_with(|| a.name().cmp(b.name()))
+    });
+    for node in nodes {
+        if let TreeNode::Directory { children, .. } = node {
+            sort(children);
+        }
+    }
+}
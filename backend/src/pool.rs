@@ -0,0 +1,144 @@
+use futures_util::FutureExt;
+use log::error;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OwnedMutexGuard;
+use turso::{Connection, Database};
+
+/// Keeps idle connections to one database around so requests don't have to
+/// share a single connection or reconnect every time
+pub struct ConnPool {
+    db: Database,
+    idle: Mutex<Vec<Connection>>,
+    max_idle: usize,
+    /// Held by the connection in a write transaction, if any
+    writer: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// A connection checked out of the pool, handed back when dropped. A
+/// transaction still open by then is rolled back first.
+pub struct PooledConn<'a> {
+    conn: Option<Connection>,
+    pool: &'a ConnPool,
+    writing: Option<OwnedMutexGuard<()>>,
+}
+
+impl ConnPool {
+    pub fn new(db: Database, max_idle: usize) -> Self {
+        ConnPool {
+            db,
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+            writer: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    pub fn get(&self) -> Result<PooledConn<'_>, turso::Error> {
+        let idle = self.idle.lock().unwrap().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => self.db.connect()?,
+        };
+
+        Ok(PooledConn {
+            conn: Some(conn),
+            pool: self,
+            writing: None,
+        })
+    }
+}
+
+impl PooledConn<'_> {
+    /// Waits until no other connection of the pool is writing. Turso has no
+    /// busy timeout, so a second writer would fail with `database is locked`
+    /// instead of waiting its turn. Every write, in a transaction or not, goes
+    /// through here. The turn lasts until `end_write` or until the connection
+    /// goes back to the pool.
+    pub async fn write(&mut self) -> Result<(), turso::Error> {
+        if self.writing.is_none() {
+            self.writing = Some(self.pool.writer.clone().lock_owned().await);
+        }
+        // Whatever this connection read before its turn may be outdated now
+        if self.is_autocommit()? {
+            end_read(self).await?;
+        }
+        Ok(())
+    }
+
+    /// Starts a transaction once it's this connection's turn to write
+    pub async fn begin(&mut self) -> Result<(), turso::Error> {
+        self.write().await?;
+        self.execute("BEGIN", ()).await?;
+        Ok(())
+    }
+
+    /// Lets the next writer go before the connection is handed back, unless
+    /// a transaction is still open
+    pub fn end_write(&mut self) {
+        if self.is_autocommit().unwrap_or(false) {
+            self.writing = None;
+        }
+    }
+}
+
+// Turso keeps the snapshot a query reads from until the query runs out of
+// rows, even if it's dropped before that. Reads on the connection would keep
+// seeing that snapshot, and a write would fail with `database is locked` once
+// another connection has committed. Any statement run to the end lets go.
+async fn end_read(conn: &Connection) -> Result<(), turso::Error> {
+    let mut rows = conn.query("SELECT 1", ()).await?;
+    while rows.next().await?.is_some() {}
+    Ok(())
+}
+
+impl Deref for PooledConn<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConn<'_> {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+
+        // A request dropped halfway, e.g. because its client went away, can
+        // leave its transaction open. Turso runs a statement without ever
+        // yielding, so the rollback finishes right here; a connection it
+        // fails on is closed instead of being handed to the next request.
+        if !conn.is_autocommit().unwrap_or(false) {
+            match conn.execute("ROLLBACK", ()).now_or_never() {
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    error!("Rolling back an abandoned transaction failed: {}", e);
+                    return;
+                }
+                None => {
+                    error!("Rolling back an abandoned transaction didn't finish");
+                    return;
+                }
+            }
+        }
+
+        // The next request shouldn't read from this one's snapshot
+        match end_read(&conn).now_or_never() {
+            Some(Ok(())) => {}
+            Some(Err(e)) => {
+                error!("Ending a read on a pooled connection failed: {}", e);
+                return;
+            }
+            None => {
+                error!("Ending a read on a pooled connection didn't finish");
+                return;
+            }
+        }
+
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_idle {
+            idle.push(conn);
+        }
+    }
+}
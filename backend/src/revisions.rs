@@ -0,0 +1,50 @@
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+use crate::db::ProblemRevision;
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineTag {
+    Equal,
+    Delete,
+    Insert,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DiffLine {
+    pub tag: LineTag,
+    pub text: String,
+}
+
+/// Line by line changes to a problem between two of its revisions
+#[derive(Serialize, Debug)]
+pub struct RevisionDiff {
+    pub from: i64,
+    pub to: i64,
+    pub name: Vec<DiffLine>,
+    pub desc: Vec<DiffLine>,
+}
+
+pub fn diff_revisions(from: &ProblemRevision, to: &ProblemRevision) -> RevisionDiff {
+    RevisionDiff {
+        from: from.id,
+        to: to.id,
+        name: diff_lines(&from.name, &to.name),
+        desc: diff_lines(&from.desc, &to.desc),
+    }
+}
+
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            tag: match change.tag() {
+                ChangeTag::Equal => LineTag::Equal,
+                ChangeTag::Delete => LineTag::Delete,
+                ChangeTag::Insert => LineTag::Insert,
+            },
+            text: change.value().trim_end_matches(['\r', '\n']).to_string(),
+        })
+        .collect()
+}
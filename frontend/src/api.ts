@@ -1,10 +1,29 @@
 import axios from "axios";
 import type {
+  Course,
+  CreateCourse,
   Problem,
   CreateProblem,
+  UpdateProblem,
+  PatchProblem,
+  ProblemRevision,
+  RevisionDiff,
+  Extension,
   Submission,
   CreateFeedback,
+  EditFeedback,
+  Feedback,
+  FeedbackEdit,
+  ProblemStats,
   ProblemWithStats,
+  User,
+  LoginRequest,
+  CreateUser,
+  TimeRange,
+  TreeNode,
+  Page,
+  ProblemQuery,
+  SubmissionQuery,
 } from "./types";
 
 const api = axios.create({
@@ -14,10 +33,78 @@ const api = axios.create({
   },
 });
 
+// Message from the JSON error body the backend sends, if there is one
+export function apiErrorMessage(err: unknown): string | undefined {
+  if (axios.isAxiosError(err)) {
+    return err.response?.data?.error;
+  }
+  return undefined;
+}
+
 export class ApiService {
+  // Account endpoints
+  async login(credentials: LoginRequest): Promise<User> {
+    const response = await api.post("/login", credentials);
+    return response.data.user;
+  }
+
+  async logout(): Promise<void> {
+    await api.post("/logout");
+  }
+
+  async getMe(): Promise<User> {
+    const response = await api.get("/me");
+    return response.data;
+  }
+
+  async createUser(user: CreateUser): Promise<{ id: number }> {
+    const response = await api.post("/users", user);
+    return response.data;
+  }
+
+  // Course endpoints
+  async getCourses(range?: TimeRange): Promise<Course[]> {
+    const response = await api.get("/courses", { params: range });
+    return response.data;
+  }
+
+  async createCourse(course: CreateCourse): Promise<{ id: number }> {
+    const response = await api.post("/courses", course);
+    return response.data;
+  }
+
+  async addCourseMember(courseId: number, userId: number): Promise<void> {
+    await api.post(`/courses/${courseId}/members`, { user: userId });
+  }
+
+  async getCourseProblems(
+    courseId: number,
+    query?: ProblemQuery,
+  ): Promise<Problem[]> {
+    const response = await api.get(`/courses/${courseId}/problems`, {
+      params: query,
+    });
+    return response.data;
+  }
+
+  async getCourseSubmissions(
+    courseId: number,
+    query?: SubmissionQuery,
+  ): Promise<Submission[]> {
+    const response = await api.get(`/courses/${courseId}/submissions`, {
+      params: query,
+    });
+    return response.data;
+  }
+
   // Problem endpoints
-  async getProblems(): Promise<Problem[]> {
-    const response = await api.get("/problems");
+  async getProblems(query?: ProblemQuery): Promise<Problem[]> {
+    const response = await api.get("/problems", { params: query });
+    return response.data;
+  }
+
+  async getProblemStats(): Promise<ProblemStats[]> {
+    const response = await api.get("/problems/stats");
     return response.data;
   }
 
@@ -31,12 +118,74 @@ export class ApiService {
     return response.data;
   }
 
+  async updateProblem(id: number, problem: UpdateProblem): Promise<Problem> {
+    const response = await api.put(`/problems/${id}`, problem);
+    return response.data;
+  }
+
+  async patchProblem(id: number, changes: PatchProblem): Promise<Problem> {
+    const response = await api.patch(`/problems/${id}`, changes);
+    return response.data;
+  }
+
+  async deleteProblem(id: number): Promise<void> {
+    await api.delete(`/problems/${id}`);
+  }
+
+  async getProblemRevisions(id: number): Promise<ProblemRevision[]> {
+    const response = await api.get(`/problems/${id}/revisions`);
+    return response.data;
+  }
+
+  async getRevisionDiff(
+    id: number,
+    from?: number,
+    to?: number,
+  ): Promise<RevisionDiff> {
+    const response = await api.get(`/problems/${id}/revisions/diff`, {
+      params: { from, to },
+    });
+    return response.data;
+  }
+
+  async getExtensions(problemId: number): Promise<Extension[]> {
+    const response = await api.get(`/problems/${problemId}/extensions`);
+    return response.data;
+  }
+
+  async grantExtension(
+    problemId: number,
+    studentId: number,
+    dueAt: string,
+  ): Promise<Extension> {
+    const response = await api.put(
+      `/problems/${problemId}/extensions/${studentId}`,
+      { due_at: dueAt },
+    );
+    return response.data;
+  }
+
+  async revokeExtension(problemId: number, studentId: number): Promise<void> {
+    await api.delete(`/problems/${problemId}/extensions/${studentId}`);
+  }
+
   // Submission endpoints
-  async getSubmissions(): Promise<Submission[]> {
-    const response = await api.get("/submissions");
+  async getSubmissions(query?: SubmissionQuery): Promise<Submission[]> {
+    const response = await api.get("/submissions", { params: query });
     return response.data;
   }
 
+  // One page of submissions and the cursor of the next, if there is one
+  async getSubmissionsPage(
+    query: SubmissionQuery,
+  ): Promise<Page<Submission>> {
+    const response = await api.get("/submissions", { params: query });
+    return {
+      items: response.data,
+      nextCursor: response.headers["x-next-cursor"] ?? null,
+    };
+  }
+
   async getSubmission(id: number): Promise<Submission> {
     const response = await api.get(`/submissions/${id}`);
     return response.data;
@@ -50,8 +199,9 @@ export class ApiService {
     const formData = new FormData();
     formData.append("comment", comment);
 
+    // Files picked as part of a folder keep their place in it
     files.forEach((file, index) => {
-      formData.append(`file${index}`, file);
+      formData.append(`file${index}`, file, file.webkitRelativePath || file.name);
     });
 
     const response = await api.post(
@@ -66,6 +216,11 @@ export class ApiService {
     return response.data;
   }
 
+  async getSubmissionTree(id: number): Promise<TreeNode[]> {
+    const response = await api.get(`/submissions/${id}/tree`);
+    return response.data;
+  }
+
   // Feedback endpoints
   async createFeedback(
     submissionId: number,
@@ -78,9 +233,24 @@ export class ApiService {
     return response.data;
   }
 
-  // File download
-  async downloadFile(hash: string): Promise<Blob> {
+  async editFeedback(id: number, edit: EditFeedback): Promise<Feedback> {
+    const response = await api.patch(`/feedback/${id}`, edit);
+    return response.data;
+  }
+
+  async retractFeedback(id: number): Promise<void> {
+    await api.delete(`/feedback/${id}`);
+  }
+
+  async getFeedbackEdits(id: number): Promise<FeedbackEdit[]> {
+    const response = await api.get(`/feedback/${id}/edits`);
+    return response.data;
+  }
+
+  // File download, named as in the given submission if there is one
+  async downloadFile(hash: string, submissionId?: number): Promise<Blob> {
     const response = await api.get(`/files/${hash}`, {
+      params: { submission: submissionId },
       responseType: "blob",
     });
     return response.data;
@@ -88,29 +258,21 @@ export class ApiService {
 
   // Enhanced methods for the frontend
   async getProblemsWithStats(): Promise<ProblemWithStats[]> {
-    const [problems, submissions] = await Promise.all([
+    const [problems, stats] = await Promise.all([
       this.getProblems(),
-      this.getSubmissions(),
+      this.getProblemStats(),
     ]);
 
-    return problems.map((problem) => {
-      const problemSubmissions = submissions.filter(
-        (s) => s.problem === problem.id,
-      );
-      const attempts = problemSubmissions.length;
-      const accepted = problemSubmissions.some((s) => s.status.accepted);
-
-      return {
-        ...problem,
-        attempts,
-        accepted,
-      };
-    });
+    const byProblem = new Map(stats.map((s) => [s.problem, s]));
+    return problems.map((problem) => ({
+      ...problem,
+      attempts: byProblem.get(problem.id)?.attempts ?? 0,
+      accepted: byProblem.get(problem.id)?.accepted ?? false,
+    }));
   }
 
   async getSubmissionsForProblem(problemId: number): Promise<Submission[]> {
-    const submissions = await this.getSubmissions();
-    return submissions.filter((s) => s.problem === problemId);
+    return this.getSubmissions({ problem: problemId });
   }
 }
 
@@ -2,6 +2,7 @@ import { createRouter, createWebHistory } from "vue-router";
 import Home from "./views/Home.vue";
 import TeacherView from "./views/TeacherView.vue";
 import StudentView from "./views/StudentView.vue";
+import Login from "./views/Login.vue";
 
 const routes = [
   {
@@ -9,6 +10,11 @@ const routes = [
     name: "Home",
     component: Home,
   },
+  {
+    path: "/login",
+    name: "Login",
+    component: Login,
+  },
   {
     path: "/teacher",
     name: "Teacher",
@@ -1,35 +1,192 @@
-export interface Problem {
+// Unset for records from before times were kept
+export interface Timestamps {
+  created_at: string | null;
+  updated_at: string | null;
+}
+
+export interface TimeRange {
+  since?: string;
+  until?: string;
+}
+
+export type SortOrder = "newest" | "oldest";
+
+// Listings without a limit return everything at once
+export interface PageQuery extends TimeRange {
+  limit?: number;
+  cursor?: string;
+  sort?: SortOrder;
+}
+
+export interface Page<T> {
+  items: T[];
+  nextCursor: string | null;
+}
+
+export interface ProblemQuery extends PageQuery {
+  course?: number;
+}
+
+export interface Course extends Timestamps {
   id: number;
   name: string;
+}
+
+export interface CreateCourse {
+  name: string;
+}
+
+export interface ProblemLimits {
+  max_files?: number | null;
+  max_file_size?: number | null;
+  max_total_size?: number | null;
+  allowed_extensions?: string[] | null;
+}
+
+// Dates are ISO 8601 strings
+export interface ProblemSchedule {
+  opens_at?: string | null;
+  soft_deadline?: string | null;
+  hard_deadline?: string | null;
+}
+
+export interface Problem
+  extends ProblemLimits,
+    ProblemSchedule,
+    Timestamps {
+  id: number;
+  course: number;
+  name: string;
+  desc: string;
+  deleted: boolean;
+  revision: number;
+  max_score: number;
+  rubric: RubricCriterion[];
+}
+
+export interface RubricCriterion {
+  id: number;
+  name: string;
+  points: number;
+}
+
+export interface NewCriterion {
+  name: string;
+  points: number;
+}
+
+export interface CriterionScore {
+  criterion: number;
+  points: number;
+}
+
+export interface ProblemRevision extends Timestamps {
+  id: number;
+  problem: number;
+  number: number;
+  name: string;
   desc: string;
 }
 
-export interface CreateProblem {
+export interface DiffLine {
+  tag: "equal" | "delete" | "insert";
+  text: string;
+}
+
+export interface RevisionDiff {
+  from: number;
+  to: number;
+  name: DiffLine[];
+  desc: DiffLine[];
+}
+
+export interface CreateProblem extends ProblemLimits, ProblemSchedule {
+  course: number;
+  name: string;
+  desc: string;
+  max_score?: number;
+  rubric?: NewCriterion[];
+}
+
+export interface UpdateProblem extends ProblemLimits, ProblemSchedule {
   name: string;
   desc: string;
+  max_score?: number;
+  rubric?: NewCriterion[];
 }
 
-export interface FileInfo {
+export type PatchProblem = Partial<UpdateProblem>;
+
+export interface Extension extends Timestamps {
+  problem: number;
+  student: number;
+  due_at: string;
+}
+
+export interface FileInfo extends Timestamps {
   id: number;
   name: string;
+  // Where the file sits in the submission, "/" separated
+  path: string;
   hash: string;
 }
 
+export type TreeNode =
+  | { type: "directory"; name: string; children: TreeNode[] }
+  | { type: "file"; name: string; path: string; id: number; hash: string };
+
 export interface Feedback {
   id: number;
+  submission: number;
   grade: number;
+  score: number | null;
+  points: CriterionScore[];
+  state: SubmissionState;
   message?: string;
+  author: number | null;
+  author_name: string | null;
+  created_at: string | null;
+  updated_at: string | null;
+}
+
+export interface FeedbackEdit {
+  message: string | null;
+  replaced_at: string;
+}
+
+export interface EditFeedback {
+  message?: string;
+}
+
+export type SubmissionState =
+  | "pending"
+  | "under_review"
+  | "accepted"
+  | "rejected"
+  | "needs_revision";
+
+export interface SubmissionQuery extends PageQuery {
+  course?: number;
+  problem?: number;
+  student?: number;
+  status?: SubmissionState;
 }
 
 export interface SubmissionStatus {
+  state: SubmissionState;
   accepted: boolean;
+  score: number | null;
+  max_score: number;
   feedbacks: Feedback[];
 }
 
-export interface Submission {
+export interface Submission extends Timestamps {
   id: number;
   comment: string;
   problem: number;
+  author: number | null;
+  revision: number | null;
+  late: boolean;
   files: FileInfo[];
   status: SubmissionStatus;
 }
@@ -39,16 +196,45 @@ export interface CreateSubmission {
 }
 
 export interface CreateFeedback {
-  grade: number;
+  state?: SubmissionState;
+  grade?: number;
+  score?: number;
+  points?: CriterionScore[];
   message?: string;
 }
 
-export interface ProblemWithStats {
+// How the submissions to a problem went; students only see their own counted
+export interface ProblemStats {
+  problem: number;
+  attempts: number;
+  accepted: boolean;
+}
+
+export interface ProblemWithStats extends ProblemSchedule {
   id: number;
+  course: number;
   name: string;
   desc: string;
+  revision: number;
   attempts: number;
   accepted: boolean;
 }
 
 export type UserRole = "teacher" | "student";
+
+export interface User extends Timestamps {
+  id: number;
+  name: string;
+  role: UserRole;
+}
+
+export interface LoginRequest {
+  name: string;
+  password: string;
+}
+
+export interface CreateUser {
+  name: string;
+  password: string;
+  role: UserRole;
+}